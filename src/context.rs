@@ -1,82 +1,531 @@
-use std::{error::Error, fmt};
-use reqwest::{Client, Response};
-use tokio::fs::{create_dir_all, metadata, read, write};
+use std::{
+    io::{self, Cursor, ErrorKind, Read},
+    path::{Component, Path, PathBuf},
+    process,
+    sync::{atomic::{AtomicUsize, Ordering}, Arc}
+};
+use futures::StreamExt;
+use reqwest::{StatusCode, header::{HeaderValue, RANGE}};
+use sha1::{Digest, Sha1};
+use tokio::{
+    fs::{create_dir_all, metadata, read, read_dir, remove_file, rename, File, OpenOptions},
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    task::spawn_blocking
+};
+use zip::ZipArchive;
 
-#[derive(Debug)]
-pub struct ContextError {}
+use crate::error::{Error, Result};
 
-impl fmt::Display for ContextError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "ContextError")
-    }
-}
+mod builder;
+mod cache;
+mod cancel;
+#[cfg(test)]
+mod fixture;
+mod inflight;
+mod lock;
+mod mirror;
+pub(crate) mod progress;
+mod retry;
+mod scheduler;
+mod store;
+mod throttle;
+mod transport;
+pub use builder::{ContextBuilder, Settings};
+pub use cancel::CancelToken;
+pub use lock::{LockKind, LockMode, RootLock};
+pub use mirror::{Mirror, Mirrors};
+pub use progress::{Event, Phase, Progress};
+pub use retry::RetryPolicy;
+pub use scheduler::{Permit, Priority, Scheduler};
+pub use store::Store;
+pub use throttle::Throttle;
+pub use transport::{ReqwestTransport, Request, Response, Transport};
 
-impl Error for ContextError {}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkMode {
+    Online,
+    Offline,
+    Fallback
+}
 
-pub struct Context { 
-    pub path: String,
-    pub client: Client,
-    pub max_concurrent: u8
+pub struct Context {
+    pub path: PathBuf,
+    pub settings: Settings,
+    pub transport: Arc<dyn Transport>,
+    pub network: NetworkMode,
+    pub mirrors: Mirrors,
+    pub store: Option<Store>,
+    pub lock_mode: LockMode,
+    pub retry: RetryPolicy,
+    pub scheduler: Arc<Scheduler>,
+    pub throttle: Arc<Throttle>,
+    pub progress: Option<UnboundedSender<Event>>,
+    lock: lock::LockSlot,
+    inflight: inflight::InFlight
 }
 
 impl Context {
-    pub async fn new(path: &str) -> Result<Self, ContextError> {
-        let ctx = Self { 
-            path: path.to_string(),
-            client: Client::new(),
-            max_concurrent: 16
-        };
-        Self::ensure_dir(&ctx, "").await?;
-        Ok(ctx)
+    pub async fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        ContextBuilder::new(path).build().await
     }
 
-    pub async fn default() -> Result<Self, ContextError> {
+    pub fn builder<P: AsRef<Path>>(path: P) -> ContextBuilder {
+        ContextBuilder::new(path)
+    }
+
+    pub async fn default() -> Result<Self> {
         Self::new("default").await
     }
 
-    pub fn path(&self, path: &str) -> String { format!("{}/{}", self.path, path) }
+    // Joins `path` onto the root without checking it; anything that may come
+    // from a version JSON or an archive must go through `resolve` instead.
+    pub fn path<P: AsRef<Path>>(&self, path: P) -> PathBuf { self.path.join(path) }
+
+    pub fn resolve<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+        let path = path.as_ref();
+        check_relative(path)?;
+        Ok(self.path.join(path))
+    }
+
+    // Installs take the root exclusively while launches share it, so neither
+    // another process nor another task can rewrite files underneath a running
+    // game. The lock isn't reentrant: anything already holding it passes it on.
+    pub async fn lock(&self, kind: LockKind) -> Result<Arc<RootLock>> {
+        self.lock.acquire(&self.path(".lock"), kind, self.lock_mode).await
+    }
+
+    pub fn subscribe(&mut self) -> UnboundedReceiver<Event> {
+        let (tx, rx) = unbounded_channel();
+        self.progress = Some(tx);
+        rx
+    }
+
+    pub fn emit(&self, event: Event) {
+        if let Some(tx) = &self.progress {
+            let _ = tx.send(event);
+        }
+    }
+
+    // Caps all downloads on this context at `limit` bytes per second, or
+    // lifts the cap with `None`. Safe to call while installs are running.
+    pub fn set_bandwidth_limit(&self, limit: Option<u64>) {
+        self.throttle.set_rate(limit);
+    }
 
-    pub async fn get(&self, url: &str) -> reqwest::Result<Response> {
-        self.client.get(url).send().await
+    // Waits for a slot in the shared download budget, giving up on `cancel`.
+    pub async fn schedule(&self, priority: Priority, cancel: &CancelToken) -> Result<Permit> {
+        cancel.run(async { Ok(self.scheduler.acquire(priority).await) }).await
     }
 
-    pub async fn read_file(&self, path: &str) -> Result<Vec<u8>, ContextError> {
-        match read(Self::path(&self, path)).await {
-            Ok(b) => Ok(b),
-            Err(_) => Err(ContextError {})
+    pub async fn send(&self, request: Request) -> Result<Response> {
+        if self.network == NetworkMode::Offline {
+            return Err(Error::Offline { url: request.url })
         }
+        let mut response = self.transport.send(request).await?;
+        // Every body is metered here, so the limit covers installers, indexes
+        // and manifests as well as library and asset downloads.
+        let throttle = self.throttle.clone();
+        response.body = response.body
+            .then(move |chunk| {
+                let throttle = throttle.clone();
+                async move {
+                    if let Ok(chunk) = &chunk { throttle.consume(chunk.len()).await }
+                    chunk
+                }
+            })
+            .boxed();
+        Ok(response)
     }
 
-    pub async fn ensure_dir(&self, path: &str) -> Result<(), ContextError> {
-        let path = Self::path(&self, path);
+    pub async fn get(&self, url: &str) -> Result<Response> {
+        self.fetch(Request::get(url)).await
+    }
+
+    // Sends `request` through the mirrors and retry policy, failing on any
+    // unsuccessful status.
+    pub async fn fetch(&self, request: Request) -> Result<Response> {
+        let request = &request;
+        self.mirrors.run(&request.url, |url| async move {
+            let request = Request { url, ..request.clone() };
+            self.retry.run(|| async {
+                self.send(request.clone()).await?.error_for_status()
+            }).await
+        }).await
+    }
+
+    pub async fn read_file<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>> {
+        let path = self.resolve(path)?;
+        read(&path).await.map_err(|e| Error::io(&path, e))
+    }
+
+    pub fn available_space(&self) -> Result<u64> {
+        fs2::available_space(&self.path).map_err(|e| Error::io(&self.path, e))
+    }
+
+    pub async fn ensure_dir<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = self.resolve(path)?;
         match metadata(&path).await {
             Ok(m) => {
                 if m.is_dir() { Ok(()) }
-                else { Err(ContextError {}) }}, 
-            Err(_) => {
-                if create_dir_all(&path).await.is_ok() { Ok(()) } 
-                else { Err(ContextError {}) }
+                else { Err(Error::NotADirectory { path }) }
+            },
+            Err(_) => create_dir_all(&path).await.map_err(|e| Error::io(&path, e))
+        }
+    }
+
+    // Lists every file below `dir` as (path relative to the root, size).
+    pub async fn walk<P: AsRef<Path>>(&self, dir: P) -> Result<Vec<(PathBuf, u64)>> {
+        let mut files = Vec::new();
+        let mut dirs = vec![dir.as_ref().to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let full = self.resolve(&dir)?;
+            let mut entries = match read_dir(&full).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(Error::io(&full, e))
+            };
+            while let Some(entry) = entries.next_entry().await.map_err(|e| Error::io(&full, e))? {
+                let path = dir.join(entry.file_name());
+                let m = entry.metadata().await.map_err(|e| Error::io(entry.path(), e))?;
+                if m.is_dir() { dirs.push(path) }
+                else { files.push((path, m.len())) }
             }
         }
+        Ok(files)
     }
 
-    pub async fn write_file(&self, path: &str, bytes: &[u8]) -> Result<(), ContextError> {
-        let mut split: Vec<&str> = path.split("/").collect();
-        if split.pop().is_some() {
-            let dir_path = split.join("/");
-            Self::ensure_dir(&self, &dir_path).await?;
-            if write(Self::path(&self, path), bytes).await.is_ok() { Ok(()) }
-            else { Err(ContextError {}) }
-        } else { Err(ContextError {}) }
+    pub async fn ensure_parent<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        match path.parent() {
+            Some(parent) => self.ensure_dir(parent).await,
+            None => Err(Error::InvalidPath { path: path.to_path_buf() })
+        }
     }
 
-    pub async fn check_file_size(&self, path: &str, size: u64) -> Result<(), ContextError> {
-        match metadata(Self::path(&self, path)).await {
-            Ok(m) => {
-                if m.len() == size { Ok(()) } 
-                else { Err(ContextError {}) }
-            },
-            Err(_) => Err(ContextError {})
+    // Writes to a temporary sibling first so `path` is only ever seen either
+    // complete or absent, even if the process dies halfway through.
+    pub async fn write_file<P: AsRef<Path>>(&self, path: P, bytes: &[u8]) -> Result<()> {
+        self.ensure_parent(&path).await?;
+        let path = self.resolve(path)?;
+        let tmp = temp_path(&path);
+        let result = async {
+            let mut file = File::create(&tmp).await?;
+            file.write_all(bytes).await?;
+            file.sync_all().await?;
+            rename(&tmp, &path).await
+        }.await;
+        if result.is_err() { let _ = remove_file(&tmp).await; }
+        result.map_err(|e| Error::io(&path, e))
+    }
+
+    pub async fn check_file_size<P: AsRef<Path>>(&self, path: P, size: u64) -> Result<()> {
+        let path = self.resolve(path)?;
+        let actual = metadata(&path).await.map_err(|e| Error::io(&path, e))?.len();
+        if actual == size { Ok(()) }
+        else { Err(Error::SizeMismatch { path, expected: size, actual }) }
+    }
+
+    pub async fn hash_file<P: AsRef<Path>>(&self, path: P) -> Result<String> {
+        let path = self.resolve(path)?;
+        let mut file = File::open(&path).await.map_err(|e| Error::io(&path, e))?;
+        let mut hasher = Sha1::new();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = file.read(&mut buf).await.map_err(|e| Error::io(&path, e))?;
+            if n == 0 { break }
+            hasher.update(&buf[..n]);
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    pub async fn check_file<P: AsRef<Path>>(&self, path: P, size: u64, sha1: &str) -> Result<()> {
+        self.check_file_size(&path, size).await?;
+        if sha1.is_empty() { return Ok(()) }
+        let actual = self.hash_file(&path).await?;
+        check_hash(self.path(path), actual, sha1)
+    }
+
+    // Fetches `url` to `path` unless it already verifies. With `entry`, `url`
+    // is a zip and only that entry of it ends up at `path`.
+    pub async fn download<P: AsRef<Path>>(
+        &self, url: &str, entry: Option<&str>, path: P, size: u64, sha1: &str, cancel: &CancelToken
+    ) -> Result<()> {
+        let path = path.as_ref();
+        self.ensure_parent(path).await?;
+        let dest = self.resolve(path)?;
+        cancel.check()?;
+        // Another task may have been fetching the same file; once it's done
+        // there's nothing left to do.
+        let _guard = cancel.run(async { Ok(self.inflight.acquire(&dest).await) }).await?;
+        if self.check_file(path, size, sha1).await.is_ok() {
+            return Ok(())
+        }
+        let store = self.store.as_ref().filter(|s| s.object_path(sha1).is_ok());
+        if let Some(store) = store {
+            // A blob is only trusted once the checked out file verifies, so
+            // a corrupted one can't spread to every root sharing the store.
+            if store.contains(sha1, size).await {
+                store.checkout(sha1, &dest).await?;
+                if self.check_file(path, size, sha1).await.is_ok() { return Ok(()) }
+                store.evict(sha1).await?;
+                let _ = remove_file(&dest).await;
+            }
+        }
+        // Versions resolved before installer entries were recorded left
+        // Forge's own jar without anywhere to be fetched from.
+        if url.is_empty() { return Err(Error::NotCached { path: dest }) }
+        let result = cancel.run(self.mirrors.run(url, |url| async move {
+            self.retry.run(|| self.download_once(&url, entry, path, size, sha1)).await
+        })).await;
+        // A cancelled download isn't worth resuming, unlike a failed one.
+        if let Err(Error::Cancelled) = result {
+            let _ = remove_file(with_suffix(&dest, ".part")).await;
+        }
+        result?;
+        match store {
+            Some(store) => store.insert(sha1, &dest).await,
+            None => Ok(())
+        }
+    }
+
+    // Streams `url` into `<path>.part`, resuming from whatever a previous
+    // attempt left behind, and only renames it into place once it verifies.
+    async fn download_once(
+        &self, url: &str, entry: Option<&str>, path: &Path, size: u64, sha1: &str
+    ) -> Result<()> {
+        let part = with_suffix(path, ".part");
+        let part_path = self.resolve(&part)?;
+        match entry {
+            Some(entry) => self.unpack_part(url, entry, &part_path).await?,
+            None => self.stream_part(url, &part_path, size).await?
+        }
+        if let Err(e) = self.check_file(&part, size, sha1).await {
+            let _ = remove_file(&part_path).await;
+            return Err(e)
+        }
+        let path = self.resolve(path)?;
+        rename(&part_path, &path).await.map_err(|e| Error::io(&path, e))
+    }
+
+    async fn stream_part(&self, url: &str, part_path: &Path, size: u64) -> Result<()> {
+        let offset = match metadata(part_path).await {
+            Ok(m) if m.len() <= size => m.len(),
+            _ => 0
+        };
+        if offset > 0 && offset == size { return Ok(()) }
+        let mut request = Request::get(url);
+        if offset > 0 {
+            let range = HeaderValue::from_str(&format!("bytes={}-", offset))
+                .map_err(|e| Error::transport(url, e))?;
+            request.headers.insert(RANGE, range);
+        }
+        let mut response = self.send(request).await?;
+        // The server's copy no longer matches what the part was cut from.
+        if offset > 0 && response.status == StatusCode::RANGE_NOT_SATISFIABLE {
+            let _ = remove_file(part_path).await;
+            response = self.send(Request::get(url)).await?;
+        }
+        let mut response = response.error_for_status()?;
+        let mut file = if response.status == StatusCode::PARTIAL_CONTENT {
+            OpenOptions::new().append(true).open(part_path).await
+        } else {
+            File::create(part_path).await
+        }.map_err(|e| Error::io(part_path, e))?;
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk).await.map_err(|e| Error::io(part_path, e))?;
         }
+        file.sync_all().await.map_err(|e| Error::io(part_path, e))
+    }
+
+    // A single entry can't be resumed, so the archive is fetched whole each
+    // time and only the entry is written out.
+    async fn unpack_part(&self, url: &str, entry: &str, part_path: &Path) -> Result<()> {
+        let archive = self.send(Request::get(url)).await?.error_for_status()?.bytes().await?;
+        let location = format!("{}!/{}", url, entry);
+        let name = entry.to_string();
+        let bytes = spawn_blocking(move || -> Result<Vec<u8>> {
+            let zip_err = |e| Error::zip(&location, e);
+            let mut zip = ZipArchive::new(Cursor::new(archive)).map_err(zip_err)?;
+            let mut file = zip.by_name(&name).map_err(zip_err)?;
+            let mut bytes = Vec::new();
+            file.read_to_end(&mut bytes).map_err(|e| zip_err(e.into()))?;
+            Ok(bytes)
+        }).await.map_err(|e| Error::io(part_path, io::Error::other(e)))??;
+        let mut file = File::create(part_path).await.map_err(|e| Error::io(part_path, e))?;
+        file.write_all(&bytes).await.map_err(|e| Error::io(part_path, e))?;
+        file.sync_all().await.map_err(|e| Error::io(part_path, e))
+    }
+}
+
+// Rejects `..`, roots and prefixes, so `path` can only name something below
+// whatever it's joined onto.
+fn check_relative(path: &Path) -> Result<()> {
+    let escapes = path.components().any(|c| {
+        !matches!(c, Component::Normal(_) | Component::CurDir)
+    });
+    if escapes { Err(Error::InvalidPath { path: path.to_path_buf() }) } else { Ok(()) }
+}
+
+pub(crate) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    with_suffix(path, &format!(".{}-{}.tmp", process::id(), count))
+}
+
+pub fn sha1(bytes: &[u8]) -> String {
+    format!("{:x}", Sha1::digest(bytes))
+}
+
+// `sha1` comes from version JSON and asset indexes, so anything but 40 hex
+// digits is rejected before it's used to build a path.
+pub(crate) fn check_sha1(sha1: &str) -> Result<()> {
+    if sha1.len() == 40 && sha1.bytes().all(|b| b.is_ascii_hexdigit()) { Ok(()) }
+    else { Err(Error::InvalidPath { path: PathBuf::from(sha1) }) }
+}
+
+fn check_hash(path: PathBuf, actual: String, expected: &str) -> Result<()> {
+    if actual.eq_ignore_ascii_case(expected) { Ok(()) }
+    else {
+        Err(Error::HashMismatch {
+            path,
+            expected: expected.to_string(),
+            actual
+        })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::CONTENT_RANGE;
+
+    use super::*;
+    use fixture::{builder, run, Fixture, Reply};
+
+    const BODY: &[u8] = b"hello, world";
+
+    fn range(request: &Request) -> Option<&str> {
+        request.headers.get(RANGE).and_then(|v| v.to_str().ok())
+    }
+
+    // Writes `part` where an earlier attempt at `file` would have left it.
+    async fn with_part(ctx: &Context, part: &[u8]) {
+        ctx.write_file("file.part", part).await.unwrap();
+    }
+
+    async fn download(ctx: &Context) -> Result<Vec<u8>> {
+        let size = BODY.len() as u64;
+        ctx.download("https://host/file", None, "file", size, &sha1(BODY), &CancelToken::new()).await?;
+        assert!(!ctx.path("file.part").exists());
+        ctx.read_file("file").await
+    }
+
+    #[test]
+    fn downloads_without_a_part() {
+        let fixture = Fixture::new(|_| Reply::new(StatusCode::OK, BODY));
+        run(async {
+            let ctx = builder("fresh", fixture.clone()).build().await.unwrap();
+            assert_eq!(download(&ctx).await.unwrap(), BODY);
+        });
+        assert_eq!(range(&fixture.requests()[0]), None);
+    }
+
+    #[test]
+    fn resumes_from_a_part() {
+        let fixture = Fixture::new(|r| match range(r) {
+            Some("bytes=5-") => Reply::new(StatusCode::PARTIAL_CONTENT, &BODY[5..])
+                .header(CONTENT_RANGE, "bytes 5-11/12"),
+            _ => Reply::new(StatusCode::OK, BODY)
+        });
+        run(async {
+            let ctx = builder("resume", fixture.clone()).build().await.unwrap();
+            with_part(&ctx, &BODY[..5]).await;
+            assert_eq!(download(&ctx).await.unwrap(), BODY);
+        });
+        let requests = fixture.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(range(&requests[0]), Some("bytes=5-"));
+    }
+
+    #[test]
+    fn starts_over_when_the_range_is_ignored() {
+        let fixture = Fixture::new(|_| Reply::new(StatusCode::OK, BODY));
+        run(async {
+            let ctx = builder("ignored-range", fixture.clone()).build().await.unwrap();
+            with_part(&ctx, &BODY[..5]).await;
+            assert_eq!(download(&ctx).await.unwrap(), BODY);
+        });
+        assert_eq!(fixture.requests().len(), 1);
+    }
+
+    #[test]
+    fn starts_over_when_the_range_is_unsatisfiable() {
+        let fixture = Fixture::new(|r| match range(r) {
+            Some(_) => Reply::new(StatusCode::RANGE_NOT_SATISFIABLE, b""),
+            None => Reply::new(StatusCode::OK, BODY)
+        });
+        run(async {
+            let ctx = builder("unsatisfiable", fixture.clone()).build().await.unwrap();
+            with_part(&ctx, b"stale").await;
+            assert_eq!(download(&ctx).await.unwrap(), BODY);
+        });
+        let requests = fixture.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(range(&requests[1]), None);
+    }
+
+    #[test]
+    fn discards_a_part_that_does_not_verify() {
+        let fixture = Fixture::new(|_| Reply::new(StatusCode::PARTIAL_CONTENT, &BODY[5..]));
+        run(async {
+            let ctx = builder("bad-part", fixture.clone()).build().await.unwrap();
+            with_part(&ctx, b"HELLO").await;
+            assert!(matches!(download(&ctx).await, Err(Error::HashMismatch { .. })));
+            assert!(!ctx.path("file").exists());
+            assert!(!ctx.path("file.part").exists());
+        });
+    }
+
+    #[test]
+    fn accepts_paths_below_the_root() {
+        assert!(check_relative(Path::new("libraries/org/lwjgl/lwjgl.jar")).is_ok());
+        assert!(check_relative(Path::new("./assets/indexes/1.16.json")).is_ok());
+        assert!(check_relative(Path::new("natives/lib..name.so")).is_ok());
+    }
+
+    #[test]
+    fn rejects_parent_segments() {
+        assert!(check_relative(Path::new("../outside")).is_err());
+        assert!(check_relative(Path::new("libraries/../../outside")).is_err());
+        assert!(check_relative(Path::new("libraries/a/..")).is_err());
+    }
+
+    #[test]
+    fn rejects_absolute_paths() {
+        assert!(check_relative(Path::new("/etc/passwd")).is_err());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn rejects_windows_prefixes() {
+        assert!(check_relative(Path::new(r"C:\Windows\System32")).is_err());
+        assert!(check_relative(Path::new(r"\\server\share\file")).is_err());
+        assert!(check_relative(Path::new(r"\Windows")).is_err());
+    }
+
+    // Zip entry names are joined onto natives/ by `extract` before they
+    // reach `resolve`, and an absolute name replaces the base entirely.
+    #[test]
+    fn rejects_escaping_zip_entries() {
+        for name in ["../evil.dll", "a/../../evil.dll", "/tmp/evil.so"].iter() {
+            assert!(check_relative(&Path::new("natives").join(name)).is_err(), "{}", name);
+        }
+        assert!(check_relative(&Path::new("natives").join("lwjgl.dll")).is_ok());
+    }
+}
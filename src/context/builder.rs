@@ -0,0 +1,191 @@
+use std::{path::{Path, PathBuf}, sync::Arc, time::Duration};
+use reqwest::{Client, Proxy};
+
+use crate::error::{Error, Result};
+use super::{
+    lock::LockSlot, Context, LockMode, Mirrors, NetworkMode,
+    ReqwestTransport, RetryPolicy, Scheduler, Store, Throttle, Transport
+};
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub user_agent: String,
+    pub connect_timeout: Option<Duration>,
+    pub timeout: Option<Duration>,
+    pub proxy: Option<String>,
+    pub cache_ttl: Duration,
+    pub library_concurrency: usize,
+    pub asset_concurrency: usize,
+    pub download_concurrency: usize,
+    pub bandwidth_limit: Option<u64>
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            user_agent: concat!("launcherlib/", env!("CARGO_PKG_VERSION")).to_string(),
+            connect_timeout: Some(Duration::from_secs(10)),
+            timeout: None,
+            proxy: None,
+            cache_ttl: Duration::from_secs(10 * 60),
+            library_concurrency: 16,
+            asset_concurrency: 16,
+            download_concurrency: 16,
+            bandwidth_limit: None
+        }
+    }
+}
+
+impl Settings {
+    pub fn client(&self) -> Result<Client> {
+        let mut builder = Client::builder().user_agent(&self.user_agent);
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(Proxy::all(proxy).map_err(|e| Error::transport(proxy, e))?);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+pub struct ContextBuilder {
+    path: PathBuf,
+    settings: Settings,
+    transport: Option<Arc<dyn Transport>>,
+    network: NetworkMode,
+    mirrors: Mirrors,
+    store: Option<Store>,
+    lock_mode: LockMode,
+    retry: RetryPolicy,
+    scheduler: Option<Arc<Scheduler>>
+}
+
+impl ContextBuilder {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            settings: Settings::default(),
+            transport: None,
+            network: NetworkMode::Fallback,
+            mirrors: Mirrors::default(),
+            store: None,
+            lock_mode: LockMode::Wait,
+            retry: RetryPolicy::default(),
+            scheduler: None
+        }
+    }
+
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.settings.user_agent = user_agent.to_string();
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.settings.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.settings.timeout = Some(timeout);
+        self
+    }
+
+    pub fn proxy(mut self, proxy: &str) -> Self {
+        self.settings.proxy = Some(proxy.to_string());
+        self
+    }
+
+    pub fn cache_ttl(mut self, ttl: Duration) -> Self {
+        self.settings.cache_ttl = ttl;
+        self
+    }
+
+    pub fn library_concurrency(mut self, limit: usize) -> Self {
+        self.settings.library_concurrency = limit.max(1);
+        self
+    }
+
+    pub fn asset_concurrency(mut self, limit: usize) -> Self {
+        self.settings.asset_concurrency = limit.max(1);
+        self
+    }
+
+    pub fn download_concurrency(mut self, limit: usize) -> Self {
+        self.settings.download_concurrency = limit.max(1);
+        self
+    }
+
+    // Bytes per second across every download; see `Context::set_bandwidth_limit`.
+    pub fn bandwidth_limit(mut self, limit: u64) -> Self {
+        self.settings.bandwidth_limit = Some(limit).filter(|l| *l > 0);
+        self
+    }
+
+    // Replaces the reqwest transport built from the HTTP settings above.
+    pub fn transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    pub fn network(mut self, network: NetworkMode) -> Self {
+        self.network = network;
+        self
+    }
+
+    pub fn mirrors(mut self, mirrors: Mirrors) -> Self {
+        self.mirrors = mirrors;
+        self
+    }
+
+    pub fn store(mut self, store: Store) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    pub fn lock_mode(mut self, lock_mode: LockMode) -> Self {
+        self.lock_mode = lock_mode;
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    // Shares one download budget between several contexts.
+    pub fn scheduler(mut self, scheduler: Arc<Scheduler>) -> Self {
+        self.scheduler = Some(scheduler);
+        self
+    }
+
+    pub async fn build(self) -> Result<Context> {
+        let transport = match self.transport {
+            Some(transport) => transport,
+            None => Arc::new(ReqwestTransport::new(self.settings.client()?))
+        };
+        let limit = self.settings.download_concurrency;
+        let scheduler = self.scheduler.unwrap_or_else(|| Scheduler::new(limit));
+        let throttle = Arc::new(Throttle::new(self.settings.bandwidth_limit));
+        let ctx = Context {
+            path: self.path,
+            settings: self.settings,
+            transport,
+            network: self.network,
+            mirrors: self.mirrors,
+            store: self.store,
+            lock_mode: self.lock_mode,
+            retry: self.retry,
+            scheduler,
+            throttle,
+            progress: None,
+            lock: LockSlot::default(),
+            inflight: Default::default()
+        };
+        ctx.ensure_dir("").await?;
+        Ok(ctx)
+    }
+}
@@ -0,0 +1,210 @@
+use std::{
+    future::Future,
+    io::ErrorKind,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH}
+};
+use reqwest::{
+    header::{HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode
+};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use super::{with_suffix, Context, NetworkMode, Request};
+
+// What we know about a cached response, stored next to its body.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Meta {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    fetched_at: u64
+}
+
+impl Context {
+    // Fetches `url` and keeps a copy under `cache/`. Copies younger than the
+    // cache TTL are served as is, older ones are revalidated with the server,
+    // and any copy is served when offline or, in fallback mode, when the
+    // network is unreachable.
+    pub async fn get_cached(&self, url: &str) -> Result<Vec<u8>> {
+        let path = Path::new("cache").join(url.split("://").last().unwrap_or(url));
+        let meta_path = with_suffix(&path, ".meta.json");
+        // Validators are only worth sending while the body they describe is
+        // still there; otherwise a 304 would leave nothing to serve.
+        let meta: Option<Meta> = match self.read_file(&meta_path).await {
+            Ok(bytes) if self.resolve(&path)?.is_file() => serde_json::from_slice(&bytes).ok(),
+            _ => None
+        };
+        if let Some(meta) = &meta {
+            if now().saturating_sub(meta.fetched_at) < self.settings.cache_ttl.as_secs() {
+                if let Ok(bytes) = self.read_file(&path).await {
+                    return Ok(bytes)
+                }
+            }
+        }
+        self.cached(&path, || async {
+            let mut request = Request::get(url);
+            if let Some(meta) = &meta {
+                if let Some(etag) = meta.etag.as_deref().and_then(header) {
+                    request.headers.insert(IF_NONE_MATCH, etag);
+                }
+                if let Some(modified) = meta.last_modified.as_deref().and_then(header) {
+                    request.headers.insert(IF_MODIFIED_SINCE, modified);
+                }
+            }
+            let response = self.fetch(request).await?;
+            let text = |name| response.headers
+                .get(name)
+                .and_then(|v: &HeaderValue| v.to_str().ok())
+                .map(|v| v.to_string());
+            let mut fresh = Meta {
+                etag: text(ETAG),
+                last_modified: text(LAST_MODIFIED),
+                fetched_at: now()
+            };
+            let bytes = if response.status == StatusCode::NOT_MODIFIED {
+                let meta = meta.unwrap_or_default();
+                fresh.etag = fresh.etag.or(meta.etag);
+                fresh.last_modified = fresh.last_modified.or(meta.last_modified);
+                self.read_file(&path).await?
+            } else {
+                let bytes = response.bytes().await?;
+                self.write_file(&path, &bytes).await?;
+                bytes
+            };
+            self.write_file(&meta_path, &serde_json::to_vec(&fresh)?).await?;
+            Ok(bytes)
+        }).await
+    }
+
+    pub async fn cached<P, F, Fut>(&self, path: P, fetch: F) -> Result<Vec<u8>>
+    where
+        P: AsRef<Path>,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Vec<u8>>>
+    {
+        if self.network == NetworkMode::Offline {
+            return self.read_cached(path).await
+        }
+        match fetch().await {
+            Err(e) if self.network == NetworkMode::Fallback && e.is_network() => {
+                self.read_cached(path).await.map_err(|_| e)
+            }
+            result => result
+        }
+    }
+
+    pub async fn read_cached<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>> {
+        self.read_file(path).await.map_err(|e| match e {
+            Error::Io { path, source } if source.kind() == ErrorKind::NotFound => {
+                Error::NotCached { path }
+            }
+            e => e
+        })
+    }
+}
+
+fn header(value: &str) -> Option<HeaderValue> {
+    HeaderValue::from_str(value).ok()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0))
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use reqwest::header::{HeaderMap, HeaderName};
+
+    use super::*;
+    use super::super::fixture::{builder, run, Fixture, Reply};
+
+    const URL: &str = "https://host/manifest.json";
+
+    fn get(headers: &HeaderMap, name: HeaderName) -> Option<&str> {
+        headers.get(name).and_then(|v| v.to_str().ok())
+    }
+
+    // Serves a new body on every full response, and 304 whenever the
+    // client's validator matches the last one handed out.
+    fn server() -> std::sync::Arc<Fixture> {
+        let version = AtomicUsize::new(0);
+        Fixture::new(move |r| {
+            let current = version.load(Ordering::SeqCst);
+            let etag = format!("\"v{}\"", current);
+            if get(&r.headers, IF_NONE_MATCH) == Some(etag.as_str()) {
+                return Reply::new(StatusCode::NOT_MODIFIED, b"")
+            }
+            let next = version.fetch_add(1, Ordering::SeqCst) + 1;
+            Reply::new(StatusCode::OK, format!("body {}", next).as_bytes())
+                .header(ETAG, &format!("\"v{}\"", next))
+                .header(LAST_MODIFIED, "Wed, 21 Oct 2015 07:28:00 GMT")
+        })
+    }
+
+    #[test]
+    fn serves_fresh_copies_without_asking() {
+        let fixture = server();
+        run(async {
+            let ctx = builder("cache-fresh", fixture.clone()).build().await.unwrap();
+            assert_eq!(ctx.get_cached(URL).await.unwrap(), b"body 1");
+            assert_eq!(ctx.get_cached(URL).await.unwrap(), b"body 1");
+        });
+        assert_eq!(fixture.requests().len(), 1);
+    }
+
+    #[test]
+    fn revalidates_stale_copies() {
+        let fixture = server();
+        run(async {
+            let ctx = builder("cache-stale", fixture.clone())
+                .cache_ttl(Duration::from_secs(0))
+                .build()
+                .await
+                .unwrap();
+            assert_eq!(ctx.get_cached(URL).await.unwrap(), b"body 1");
+            assert_eq!(ctx.get_cached(URL).await.unwrap(), b"body 1");
+        });
+        let requests = fixture.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(get(&requests[0].headers, IF_NONE_MATCH), None);
+        assert_eq!(get(&requests[1].headers, IF_NONE_MATCH), Some("\"v1\""));
+        assert_eq!(get(&requests[1].headers, IF_MODIFIED_SINCE), Some("Wed, 21 Oct 2015 07:28:00 GMT"));
+    }
+
+    #[test]
+    fn refetches_without_validators_when_the_body_is_gone() {
+        let fixture = server();
+        run(async {
+            let ctx = builder("cache-lost", fixture.clone())
+                .cache_ttl(Duration::from_secs(0))
+                .build()
+                .await
+                .unwrap();
+            assert_eq!(ctx.get_cached(URL).await.unwrap(), b"body 1");
+            std::fs::remove_file(ctx.path("cache/host/manifest.json")).unwrap();
+            assert_eq!(ctx.get_cached(URL).await.unwrap(), b"body 2");
+        });
+        assert_eq!(get(&fixture.requests()[1].headers, IF_NONE_MATCH), None);
+    }
+
+    #[test]
+    fn serves_the_cached_copy_offline() {
+        let fixture = server();
+        run(async {
+            let mut ctx = builder("cache-offline", fixture.clone())
+                .cache_ttl(Duration::from_secs(0))
+                .build()
+                .await
+                .unwrap();
+            assert_eq!(ctx.get_cached(URL).await.unwrap(), b"body 1");
+            ctx.network = NetworkMode::Offline;
+            assert_eq!(ctx.get_cached(URL).await.unwrap(), b"body 1");
+        });
+        assert_eq!(fixture.requests().len(), 1);
+    }
+}
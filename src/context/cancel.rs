@@ -0,0 +1,60 @@
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+use futures::{future::{self, Either}, pin_mut, Future};
+use tokio::sync::Notify;
+
+use crate::error::{Error, Result};
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify
+}
+
+// Cheap to clone; every clone observes the same `cancel` call.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<Inner>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        self.0.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() { Err(Error::Cancelled) } else { Ok(()) }
+    }
+
+    pub fn cancelled(&self) -> impl Future<Output = ()> + Send + 'static {
+        let inner = self.0.clone();
+        async move {
+            loop {
+                let notified = inner.notify.notified();
+                if inner.cancelled.load(Ordering::SeqCst) { return }
+                notified.await;
+            }
+        }
+    }
+
+    // Drives `f` until it finishes or the token is cancelled, dropping it in
+    // the latter case.
+    pub async fn run<F, T>(&self, f: F) -> Result<T>
+    where
+        F: Future<Output = Result<T>>
+    {
+        self.check()?;
+        let cancelled = self.cancelled();
+        pin_mut!(f, cancelled);
+        match future::select(f, cancelled).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => Err(Error::Cancelled)
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    fs,
+    future::Future,
+    process,
+    sync::{Arc, Mutex}
+};
+use bytes::Bytes;
+use futures::{future::BoxFuture, stream, FutureExt, StreamExt};
+use reqwest::{header::{HeaderMap, HeaderName, HeaderValue}, StatusCode};
+
+use crate::error::Result;
+use super::{Context, ContextBuilder, NetworkMode, Request, Response, RetryPolicy, Transport};
+
+pub(crate) struct Reply {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>
+}
+
+impl Reply {
+    pub fn new(status: StatusCode, body: &[u8]) -> Self {
+        Self { status, headers: HeaderMap::new(), body: body.to_vec() }
+    }
+
+    pub fn header(mut self, name: HeaderName, value: &str) -> Self {
+        self.headers.insert(name, HeaderValue::from_str(value).unwrap());
+        self
+    }
+}
+
+// An in-memory transport that answers every request through `respond` and
+// keeps the requests it was sent.
+pub(crate) struct Fixture {
+    respond: Box<dyn Fn(&Request) -> Reply + Send + Sync>,
+    pub requests: Mutex<Vec<Request>>
+}
+
+impl Fixture {
+    pub fn new<F>(respond: F) -> Arc<Self>
+    where
+        F: Fn(&Request) -> Reply + Send + Sync + 'static
+    {
+        Arc::new(Self { respond: Box::new(respond), requests: Mutex::default() })
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Transport for Fixture {
+    fn send(&self, request: Request) -> BoxFuture<'_, Result<Response>> {
+        let reply = (self.respond)(&request);
+        let url = request.url.clone();
+        self.requests.lock().unwrap().push(request);
+        let Reply { status, headers, body } = reply;
+        let body = stream::iter(vec![Ok(Bytes::from(body))]).boxed();
+        async move { Ok(Response { url, status, headers, body }) }.boxed()
+    }
+}
+
+// Builds a fresh, online context under the temp dir that talks only to
+// `fixture` and doesn't retry.
+pub(crate) fn builder(name: &str, fixture: Arc<Fixture>) -> ContextBuilder {
+    let path = std::env::temp_dir().join(format!("launcherlib-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&path);
+    Context::builder(path)
+        .transport(fixture)
+        .network(NetworkMode::Online)
+        .retry(RetryPolicy::none())
+}
+
+// The tokio file APIs need a runtime to run on.
+pub(crate) fn run<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
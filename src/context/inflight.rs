@@ -0,0 +1,29 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex as SyncMutex, Weak}
+};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+// One async mutex per destination currently being written, so concurrent
+// installs through the same context wait on each other instead of racing.
+#[derive(Default)]
+pub(crate) struct InFlight(SyncMutex<HashMap<PathBuf, Weak<Mutex<()>>>>);
+
+impl InFlight {
+    pub async fn acquire(&self, path: &Path) -> OwnedMutexGuard<()> {
+        let mutex = {
+            let mut map = self.0.lock().unwrap();
+            map.retain(|_, m| m.strong_count() > 0);
+            match map.get(path).and_then(Weak::upgrade) {
+                Some(mutex) => mutex,
+                None => {
+                    let mutex = Arc::new(Mutex::new(()));
+                    map.insert(path.to_path_buf(), Arc::downgrade(&mutex));
+                    mutex
+                }
+            }
+        };
+        mutex.lock_owned().await
+    }
+}
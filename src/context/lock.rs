@@ -0,0 +1,117 @@
+use std::{
+    fs::{File, OpenOptions},
+    path::Path,
+    sync::{Arc, Weak},
+    time::Duration
+};
+use fs2::{lock_contended_error, FileExt};
+use tokio::{sync::Mutex, time::sleep};
+
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    Wait,
+    Fail
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    Shared,
+    Exclusive
+}
+
+// An advisory lock on a context root, released when the last clone is dropped.
+#[derive(Debug)]
+pub struct RootLock {
+    file: File,
+    pub kind: LockKind
+}
+
+impl Drop for RootLock {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+// Tracks the lock a context holds so concurrent launches share one Shared
+// lock. Anything else waits for the holder to let go in `LockMode::Wait`, so
+// an install in progress also keeps this process's own launches out; calls
+// made while holding the lock must be handed it rather than ask again.
+#[derive(Debug, Default)]
+pub(crate) struct LockSlot(Mutex<Weak<RootLock>>);
+
+impl LockSlot {
+    pub async fn acquire(&self, path: &Path, kind: LockKind, mode: LockMode) -> Result<Arc<RootLock>> {
+        let mut slot = loop {
+            let slot = self.0.lock().await;
+            let held = match slot.upgrade() { Some(lock) => lock, None => break slot };
+            if held.kind == LockKind::Shared && kind == LockKind::Shared {
+                return Ok(held)
+            }
+            if mode == LockMode::Fail {
+                return Err(Error::Locked { path: path.to_path_buf() })
+            }
+            drop(held);
+            drop(slot);
+            sleep(Duration::from_millis(100)).await;
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .map_err(|e| Error::io(path, e))?;
+        loop {
+            let result = match kind {
+                LockKind::Shared => FileExt::try_lock_shared(&file),
+                LockKind::Exclusive => FileExt::try_lock_exclusive(&file)
+            };
+            match result {
+                Ok(_) => break,
+                Err(e) if e.kind() == lock_contended_error().kind() => {
+                    if mode == LockMode::Fail {
+                        return Err(Error::Locked { path: path.to_path_buf() })
+                    }
+                    sleep(Duration::from_millis(100)).await;
+                }
+                Err(e) => return Err(Error::io(path, e))
+            }
+        }
+        let lock = Arc::new(RootLock { file, kind });
+        *slot = Arc::downgrade(&lock);
+        Ok(lock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn lock_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("launcherlib-{}-{}.lock", name, std::process::id()))
+    }
+
+    #[test]
+    fn launches_share_one_lock() {
+        let path = lock_path("shared");
+        let slot = LockSlot::default();
+        let first = block_on(slot.acquire(&path, LockKind::Shared, LockMode::Fail)).unwrap();
+        let second = block_on(slot.acquire(&path, LockKind::Shared, LockMode::Fail)).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn an_install_keeps_launches_out() {
+        let path = lock_path("exclusive");
+        let slot = LockSlot::default();
+        let install = block_on(slot.acquire(&path, LockKind::Exclusive, LockMode::Fail)).unwrap();
+        let launch = block_on(slot.acquire(&path, LockKind::Shared, LockMode::Fail));
+        assert!(matches!(launch, Err(Error::Locked { .. })));
+        let nested = block_on(slot.acquire(&path, LockKind::Exclusive, LockMode::Fail));
+        assert!(matches!(nested, Err(Error::Locked { .. })));
+        drop(install);
+        assert!(block_on(slot.acquire(&path, LockKind::Shared, LockMode::Fail)).is_ok());
+    }
+}
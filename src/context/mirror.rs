@@ -0,0 +1,134 @@
+use std::future::Future;
+
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone)]
+pub struct Mirror {
+    pub origin: String,
+    pub replacement: String
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Mirrors {
+    pub rules: Vec<Mirror>,
+    pub fallback: bool
+}
+
+impl Mirrors {
+    pub fn bmclapi() -> Self {
+        let mut mirrors = Self { rules: Vec::new(), fallback: true };
+        mirrors
+            .add("https://launchermeta.mojang.com", "https://bmclapi2.bangbang93.com")
+            .add("https://launcher.mojang.com", "https://bmclapi2.bangbang93.com")
+            .add("https://piston-meta.mojang.com", "https://bmclapi2.bangbang93.com")
+            .add("https://piston-data.mojang.com", "https://bmclapi2.bangbang93.com")
+            .add("https://libraries.minecraft.net", "https://bmclapi2.bangbang93.com/maven")
+            .add("http://resources.download.minecraft.net", "https://bmclapi2.bangbang93.com/assets")
+            .add("https://maven.minecraftforge.net", "https://bmclapi2.bangbang93.com/maven")
+            .add("https://files.minecraftforge.net/maven", "https://bmclapi2.bangbang93.com/maven");
+        mirrors
+    }
+
+    pub fn add(&mut self, origin: &str, replacement: &str) -> &mut Self {
+        self.rules.push(Mirror {
+            origin: origin.trim_end_matches('/').to_string(),
+            replacement: replacement.trim_end_matches('/').to_string()
+        });
+        self
+    }
+
+    pub fn rewrite(&self, url: &str) -> Option<String> {
+        self.rules.iter().find_map(|m| {
+            let rest = url.strip_prefix(&m.origin)?;
+            if rest.is_empty() || rest.starts_with('/') || rest.starts_with('?') {
+                Some(format!("{}{}", m.replacement, rest))
+            } else { None }
+        })
+    }
+
+    pub fn candidates(&self, url: &str) -> Vec<String> {
+        match self.rewrite(url) {
+            Some(mirrored) if self.fallback => vec![mirrored, url.to_string()],
+            Some(mirrored) => vec![mirrored],
+            None => vec![url.to_string()]
+        }
+    }
+
+    // Runs `f` against each candidate for `url` in turn until one succeeds.
+    pub async fn run<F, Fut, T>(&self, url: &str, mut f: F) -> Result<T>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: Future<Output = Result<T>>
+    {
+        let mut candidates = self.candidates(url).into_iter().peekable();
+        loop {
+            let candidate = candidates.next().unwrap_or_else(|| url.to_string());
+            match f(candidate).await {
+                Err(Error::Offline { url }) => return Err(Error::Offline { url }),
+                Err(_) if candidates.peek().is_some() => continue,
+                result => return result
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mirrors(fallback: bool) -> Mirrors {
+        let mut mirrors = Mirrors { rules: Vec::new(), fallback };
+        mirrors.add("https://libraries.minecraft.net/", "https://mirror.example/maven/");
+        mirrors
+    }
+
+    #[test]
+    fn rewrites_matching_prefixes() {
+        let mirrors = mirrors(false);
+        assert_eq!(
+            mirrors.rewrite("https://libraries.minecraft.net/org/lwjgl/lwjgl.jar").as_deref(),
+            Some("https://mirror.example/maven/org/lwjgl/lwjgl.jar")
+        );
+        assert_eq!(
+            mirrors.rewrite("https://libraries.minecraft.net?x=1").as_deref(),
+            Some("https://mirror.example/maven?x=1")
+        );
+        assert_eq!(
+            mirrors.rewrite("https://libraries.minecraft.net").as_deref(),
+            Some("https://mirror.example/maven")
+        );
+    }
+
+    #[test]
+    fn only_rewrites_whole_hosts() {
+        let mirrors = mirrors(false);
+        assert_eq!(mirrors.rewrite("https://libraries.minecraft.net.evil.example/a"), None);
+        assert_eq!(mirrors.rewrite("http://libraries.minecraft.net/a"), None);
+        assert_eq!(mirrors.rewrite("https://maven.minecraftforge.net/a"), None);
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let mut mirrors = Mirrors::default();
+        mirrors
+            .add("https://files.minecraftforge.net/maven", "https://a.example")
+            .add("https://files.minecraftforge.net", "https://b.example");
+        assert_eq!(
+            mirrors.rewrite("https://files.minecraftforge.net/maven/x").as_deref(),
+            Some("https://a.example/x")
+        );
+        assert_eq!(
+            mirrors.rewrite("https://files.minecraftforge.net/other").as_deref(),
+            Some("https://b.example/other")
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_origin_when_enabled() {
+        let url = "https://libraries.minecraft.net/a.jar";
+        let mirrored = "https://mirror.example/maven/a.jar".to_string();
+        assert_eq!(mirrors(true).candidates(url), vec![mirrored.clone(), url.to_string()]);
+        assert_eq!(mirrors(false).candidates(url), vec![mirrored]);
+        assert_eq!(mirrors(true).candidates("https://other.example/a"), vec!["https://other.example/a"]);
+    }
+}
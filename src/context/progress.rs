@@ -0,0 +1,74 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering}
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::Context;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Phase {
+    Libraries,
+    Natives,
+    Assets
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    pub phase: Phase,
+    pub completed_files: usize,
+    pub total_files: usize,
+    pub completed_bytes: u64,
+    pub total_bytes: u64
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    PhaseStarted { phase: Phase, total_files: usize, total_bytes: u64 },
+    FileStarted { phase: Phase, path: PathBuf, size: u64 },
+    FileFinished { phase: Phase, path: PathBuf, size: u64, ok: bool },
+    Progress(Progress),
+    PhaseFinished { phase: Phase, failed: usize }
+}
+
+pub(crate) struct Tracker<'a> {
+    ctx: &'a Context,
+    phase: Phase,
+    total_files: usize,
+    total_bytes: u64,
+    completed_files: AtomicUsize,
+    completed_bytes: AtomicU64
+}
+
+impl<'a> Tracker<'a> {
+    pub fn new(ctx: &'a Context, phase: Phase, total_files: usize, total_bytes: u64) -> Self {
+        ctx.emit(Event::PhaseStarted { phase, total_files, total_bytes });
+        Self {
+            ctx, phase, total_files, total_bytes,
+            completed_files: AtomicUsize::new(0),
+            completed_bytes: AtomicU64::new(0)
+        }
+    }
+
+    pub fn start(&self, path: &Path, size: u64) {
+        self.ctx.emit(Event::FileStarted { phase: self.phase, path: path.to_path_buf(), size });
+    }
+
+    pub fn finish(&self, path: &Path, size: u64, ok: bool) {
+        let completed_files = self.completed_files.fetch_add(1, Ordering::SeqCst) + 1;
+        let completed_bytes = self.completed_bytes.fetch_add(size, Ordering::SeqCst) + size;
+        self.ctx.emit(Event::FileFinished { phase: self.phase, path: path.to_path_buf(), size, ok });
+        self.ctx.emit(Event::Progress(Progress {
+            phase: self.phase,
+            completed_files,
+            total_files: self.total_files,
+            completed_bytes,
+            total_bytes: self.total_bytes
+        }));
+    }
+
+    pub fn done(&self, failed: usize) {
+        self.ctx.emit(Event::PhaseFinished { phase: self.phase, failed });
+    }
+}
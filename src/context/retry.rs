@@ -0,0 +1,68 @@
+use std::{future::Future, time::Duration};
+use reqwest::StatusCode;
+use tokio::time::sleep;
+
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    pub retryable: Vec<StatusCode>
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            retryable: vec![
+                StatusCode::REQUEST_TIMEOUT,
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT
+            ]
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self { attempts: 1, ..Self::default() }
+    }
+
+    pub fn is_retryable(&self, error: &Error) -> bool {
+        match error {
+            Error::Http { status: Some(status), .. } => self.retryable.contains(status),
+            Error::Http { status: None, .. } => true,
+            Error::SizeMismatch { .. } | Error::HashMismatch { .. } => true,
+            _ => false
+        }
+    }
+
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+
+    pub async fn run<F, Fut, T>(&self, mut f: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>
+    {
+        let mut attempt = 1;
+        loop {
+            match f().await {
+                Err(e) if attempt < self.attempts && self.is_retryable(&e) => {
+                    sleep(self.delay(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result
+            }
+        }
+    }
+}
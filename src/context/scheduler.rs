@@ -0,0 +1,185 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    sync::{Arc, Mutex}
+};
+use tokio::sync::oneshot;
+
+use super::Phase;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    Normal,
+    High
+}
+
+impl From<Phase> for Priority {
+    // The client jar and libraries gate launching, so they go ahead of assets.
+    fn from(phase: Phase) -> Self {
+        match phase {
+            Phase::Libraries => Priority::High,
+            Phase::Natives => Priority::Normal,
+            Phase::Assets => Priority::Low
+        }
+    }
+}
+
+struct Waiter {
+    priority: Priority,
+    seq: u64,
+    tx: oneshot::Sender<()>
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl Ord for Waiter {
+    // Highest priority first, then first come first served.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority.cmp(&other.priority).then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+#[derive(Default)]
+struct State {
+    running: usize,
+    seq: u64,
+    queue: BinaryHeap<Waiter>
+}
+
+// One download budget shared by every install going through it, handing
+// free slots to the highest priority waiter.
+pub struct Scheduler {
+    limit: usize,
+    state: Mutex<State>
+}
+
+impl Scheduler {
+    pub fn new(limit: usize) -> Arc<Self> {
+        Arc::new(Self { limit: limit.max(1), state: Mutex::default() })
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub async fn acquire(self: &Arc<Self>, priority: Priority) -> Permit {
+        let rx = {
+            let mut state = self.state.lock().unwrap();
+            if state.running < self.limit {
+                state.running += 1;
+                return Permit(self.clone())
+            }
+            let (tx, rx) = oneshot::channel();
+            let seq = state.seq;
+            state.seq += 1;
+            state.queue.push(Waiter { priority, seq, tx });
+            rx
+        };
+        let mut pending = Pending { scheduler: self, rx: Some(rx) };
+        let _ = pending.rx.as_mut().unwrap().await;
+        pending.rx = None;
+        Permit(self.clone())
+    }
+
+    // Passes the slot to the next live waiter, or frees it if there is none.
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        while let Some(waiter) = state.queue.pop() {
+            if waiter.tx.send(()).is_ok() { return }
+        }
+        state.running -= 1;
+    }
+}
+
+// Gives back a slot that was handed over after the waiting task gave up.
+struct Pending<'a> {
+    scheduler: &'a Scheduler,
+    rx: Option<oneshot::Receiver<()>>
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if let Some(mut rx) = self.rx.take() {
+            rx.close();
+            if rx.try_recv().is_ok() { self.scheduler.release() }
+        }
+    }
+}
+
+pub struct Permit(Arc<Scheduler>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{executor::block_on, poll, task::Poll, FutureExt};
+
+    use super::*;
+
+    #[test]
+    fn grants_up_to_the_limit() {
+        let scheduler = Scheduler::new(2);
+        let a = scheduler.acquire(Priority::Low).now_or_never();
+        let b = scheduler.acquire(Priority::Low).now_or_never();
+        assert!(a.is_some() && b.is_some());
+        assert!(scheduler.acquire(Priority::High).now_or_never().is_none());
+        drop(a);
+        assert!(scheduler.acquire(Priority::High).now_or_never().is_some());
+    }
+
+    #[test]
+    fn hands_slots_to_the_highest_priority_first() {
+        block_on(async {
+            let scheduler = Scheduler::new(1);
+            let held = scheduler.acquire(Priority::Low).await;
+            let mut low = Box::pin(scheduler.acquire(Priority::Low));
+            let mut first = Box::pin(scheduler.acquire(Priority::High));
+            let mut second = Box::pin(scheduler.acquire(Priority::High));
+            assert!(poll!(&mut low).is_pending());
+            assert!(poll!(&mut first).is_pending());
+            assert!(poll!(&mut second).is_pending());
+            drop(held);
+            let permit = match poll!(&mut first) { Poll::Ready(p) => p, _ => panic!("first") };
+            assert!(poll!(&mut second).is_pending());
+            assert!(poll!(&mut low).is_pending());
+            drop(permit);
+            let permit = match poll!(&mut second) { Poll::Ready(p) => p, _ => panic!("second") };
+            assert!(poll!(&mut low).is_pending());
+            drop(permit);
+            assert!(poll!(&mut low).is_ready());
+        });
+    }
+
+    #[test]
+    fn abandoned_waiters_give_their_slot_back() {
+        block_on(async {
+            let scheduler = Scheduler::new(1);
+            let held = scheduler.acquire(Priority::Low).await;
+            let mut waiter = Box::pin(scheduler.acquire(Priority::High));
+            assert!(poll!(&mut waiter).is_pending());
+            // The slot is handed over here, before the waiter sees it.
+            drop(held);
+            drop(waiter);
+            assert!(scheduler.acquire(Priority::Low).now_or_never().is_some());
+        });
+    }
+
+    #[test]
+    fn phases_map_to_priorities() {
+        assert!(Priority::from(Phase::Libraries) > Priority::from(Phase::Natives));
+        assert!(Priority::from(Phase::Natives) > Priority::from(Phase::Assets));
+    }
+}
@@ -0,0 +1,72 @@
+use std::{io::ErrorKind, path::{Path, PathBuf}};
+use tokio::fs::{copy, create_dir_all, hard_link, metadata, remove_file, rename};
+
+use crate::error::{Error, Result};
+use super::{check_sha1, temp_path};
+
+// A directory of blobs keyed by SHA-1 that several context roots can share.
+// Files are hard linked into each root, or copied where linking isn't possible.
+#[derive(Debug, Clone)]
+pub struct Store {
+    pub path: PathBuf
+}
+
+impl Store {
+    pub async fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        create_dir_all(path).await.map_err(|e| Error::io(path, e))?;
+        Ok(Self { path: path.to_path_buf() })
+    }
+
+    pub fn object_path(&self, sha1: &str) -> Result<PathBuf> {
+        check_sha1(sha1)?;
+        let sha1 = sha1.to_ascii_lowercase();
+        Ok(self.path.join(&sha1[0..2]).join(sha1))
+    }
+
+    pub async fn contains(&self, sha1: &str, size: u64) -> bool {
+        let object = match self.object_path(sha1) { Ok(object) => object, Err(_) => return false };
+        match metadata(object).await {
+            Ok(m) => m.is_file() && m.len() == size,
+            Err(_) => false
+        }
+    }
+
+    // Links the blob for `sha1` to `dest`, replacing whatever is there.
+    pub async fn checkout(&self, sha1: &str, dest: &Path) -> Result<()> {
+        link_or_copy(&self.object_path(sha1)?, dest).await
+    }
+
+    // Drops a blob that turned out not to match its hash.
+    pub async fn evict(&self, sha1: &str) -> Result<()> {
+        let object = self.object_path(sha1)?;
+        match remove_file(&object).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(Error::io(&object, e)),
+            _ => Ok(())
+        }
+    }
+
+    // Adds the already verified file at `src` to the store under `sha1`.
+    pub async fn insert(&self, sha1: &str, src: &Path) -> Result<()> {
+        let object = self.object_path(sha1)?;
+        if let Some(dir) = object.parent() {
+            create_dir_all(dir).await.map_err(|e| Error::io(dir, e))?;
+        }
+        link_or_copy(src, &object).await
+    }
+}
+
+async fn link_or_copy(src: &Path, dest: &Path) -> Result<()> {
+    match remove_file(dest).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(Error::io(dest, e)),
+        _ => {}
+    }
+    if hard_link(src, dest).await.is_ok() { return Ok(()) }
+    let tmp = temp_path(dest);
+    let result = match copy(src, &tmp).await {
+        Ok(_) => rename(&tmp, dest).await,
+        Err(e) => Err(e)
+    };
+    if result.is_err() { let _ = remove_file(&tmp).await; }
+    result.map_err(|e| Error::io(dest, e))
+}
@@ -0,0 +1,47 @@
+use std::{sync::Mutex, time::Duration};
+use tokio::time::{sleep, Instant};
+
+struct Bucket {
+    rate: Option<u64>,
+    tokens: f64,
+    last: Instant
+}
+
+// A token bucket shared by every download on a context. Callers take what
+// they received and sleep off any debt, so the combined rate stays at `rate`
+// bytes per second with up to one second of burst.
+pub struct Throttle(Mutex<Bucket>);
+
+impl Throttle {
+    pub fn new(rate: Option<u64>) -> Self {
+        let rate = rate.filter(|r| *r > 0);
+        let tokens = rate.unwrap_or(0) as f64;
+        Self(Mutex::new(Bucket { rate, tokens, last: Instant::now() }))
+    }
+
+    pub fn rate(&self) -> Option<u64> {
+        self.0.lock().unwrap().rate
+    }
+
+    // Takes effect for the next chunk of every running download.
+    pub fn set_rate(&self, rate: Option<u64>) {
+        let mut bucket = self.0.lock().unwrap();
+        bucket.rate = rate.filter(|r| *r > 0);
+        bucket.tokens = bucket.tokens.min(bucket.rate.unwrap_or(0) as f64);
+        bucket.last = Instant::now();
+    }
+
+    pub async fn consume(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.0.lock().unwrap();
+            let rate = match bucket.rate { Some(rate) => rate as f64, None => return };
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.last).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(rate) - bytes as f64;
+            bucket.last = now;
+            if bucket.tokens >= 0.0 { return }
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        };
+        sleep(wait).await;
+    }
+}
@@ -0,0 +1,94 @@
+use bytes::Bytes;
+use futures::{future::BoxFuture, stream::{self, BoxStream}, FutureExt, StreamExt};
+use reqwest::{header::HeaderMap, Client, StatusCode};
+use serde::de::DeserializeOwned;
+
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub url: String,
+    pub headers: HeaderMap
+}
+
+impl Request {
+    pub fn get(url: &str) -> Self {
+        Self { url: url.to_string(), headers: HeaderMap::new() }
+    }
+}
+
+pub struct Response {
+    pub url: String,
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: BoxStream<'static, Result<Bytes>>
+}
+
+impl Response {
+    pub fn error_for_status(self) -> Result<Self> {
+        if self.status.is_client_error() || self.status.is_server_error() {
+            Err(Error::status(&self.url, self.status))
+        } else { Ok(self) }
+    }
+
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>> {
+        self.body.next().await.transpose()
+    }
+
+    pub async fn bytes(mut self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(bytes)
+    }
+
+    pub async fn text(self) -> Result<String> {
+        let url = self.url.clone();
+        String::from_utf8(self.bytes().await?).map_err(|e| Error::transport(&url, e))
+    }
+
+    pub async fn json<T: DeserializeOwned>(self) -> Result<T> {
+        let url = self.url.clone();
+        serde_json::from_slice(&self.bytes().await?).map_err(|e| Error::json(&url, e))
+    }
+}
+
+pub trait Transport: Send + Sync {
+    fn send(&self, request: Request) -> BoxFuture<'_, Result<Response>>;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestTransport {
+    pub client: Client
+}
+
+impl ReqwestTransport {
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn send(&self, request: Request) -> BoxFuture<'_, Result<Response>> {
+        async move {
+            let response = self.client
+                .get(&request.url)
+                .headers(request.headers)
+                .send()
+                .await?;
+            let url = request.url;
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body = stream::unfold(Some(response), |response| async move {
+                let mut response = response?;
+                match response.chunk().await {
+                    Ok(Some(chunk)) => Some((Ok(chunk), Some(response))),
+                    Ok(None) => None,
+                    Err(e) => Some((Err(e.into()), None))
+                }
+            }).boxed();
+            Ok(Response { url, status, headers, body })
+        }.boxed()
+    }
+}
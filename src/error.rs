@@ -0,0 +1,147 @@
+use std::{error, fmt, io, path::{Path, PathBuf}};
+use reqwest::StatusCode;
+use zip::result::ZipError;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Io { path: PathBuf, source: io::Error },
+    NotADirectory { path: PathBuf },
+    Locked { path: PathBuf },
+    InvalidPath { path: PathBuf },
+    SizeMismatch { path: PathBuf, expected: u64, actual: u64 },
+    HashMismatch { path: PathBuf, expected: String, actual: String },
+    Outdated { path: PathBuf },
+    InsufficientSpace { path: PathBuf, needed: u64, available: u64 },
+    Http {
+        url: String,
+        status: Option<StatusCode>,
+        source: Option<Box<dyn error::Error + Send + Sync>>
+    },
+    Offline { url: String },
+    Cancelled,
+    NotCached { path: PathBuf },
+    Json { location: Option<String>, source: serde_json::Error },
+    Xml { location: String, source: serde_xml_rs::Error },
+    Zip { location: String, source: ZipError },
+    VersionNotFound { id: String },
+    NativeNotFound { library: String, classifier: String },
+}
+
+impl Error {
+    pub fn io<P: AsRef<Path>>(path: P, source: io::Error) -> Self {
+        Error::Io { path: path.as_ref().to_path_buf(), source }
+    }
+
+    pub fn status(url: &str, status: StatusCode) -> Self {
+        Error::Http { url: url.to_string(), status: Some(status), source: None }
+    }
+
+    pub fn transport<E>(url: &str, source: E) -> Self
+    where
+        E: Into<Box<dyn error::Error + Send + Sync>>
+    {
+        Error::Http { url: url.to_string(), status: None, source: Some(source.into()) }
+    }
+
+    pub fn is_network(&self) -> bool {
+        match self {
+            Error::Http { status: None, .. } => true,
+            Error::Http { status: Some(status), .. } => status.is_server_error(),
+            _ => false
+        }
+    }
+
+    pub fn json<L: fmt::Display>(location: L, source: serde_json::Error) -> Self {
+        Error::Json { location: Some(location.to_string()), source }
+    }
+
+    pub fn zip<L: fmt::Display>(location: L, source: ZipError) -> Self {
+        Error::Zip { location: location.to_string(), source }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => {
+                write!(f, "I/O error on {}: {}", path.display(), source)
+            }
+            Error::NotADirectory { path } => {
+                write!(f, "{} exists but is not a directory", path.display())
+            }
+            Error::Locked { path } => {
+                write!(f, "{} is locked by another process", path.display())
+            }
+            Error::InvalidPath { path } => write!(f, "invalid path {}", path.display()),
+            Error::SizeMismatch { path, expected, actual } => write!(
+                f, "size mismatch on {}: expected {} bytes, found {}",
+                path.display(), expected, actual
+            ),
+            Error::HashMismatch { path, expected, actual } => write!(
+                f, "SHA-1 mismatch on {}: expected {}, found {}",
+                path.display(), expected, actual
+            ),
+            Error::Outdated { path } => {
+                write!(f, "{} does not match the version it belongs to", path.display())
+            }
+            Error::InsufficientSpace { path, needed, available } => write!(
+                f, "{} needs {} bytes but only {} are available",
+                path.display(), needed, available
+            ),
+            Error::Http { url, status: Some(status), .. } => {
+                write!(f, "request to {} failed with status {}", url, status)
+            }
+            Error::Http { url, source: Some(source), .. } => {
+                write!(f, "request to {} failed: {}", url, source)
+            }
+            Error::Http { url, .. } => write!(f, "request to {} failed", url),
+            Error::Offline { url } => write!(f, "cannot fetch {} while offline", url),
+            Error::Cancelled => write!(f, "operation was cancelled"),
+            Error::NotCached { path } => {
+                write!(f, "{} is not available offline", path.display())
+            }
+            Error::Json { location: Some(location), source } => {
+                write!(f, "invalid JSON in {}: {}", location, source)
+            }
+            Error::Json { source, .. } => write!(f, "invalid JSON: {}", source),
+            Error::Xml { location, source } => write!(f, "invalid XML in {}: {}", location, source),
+            Error::Zip { location, source } => write!(f, "invalid archive {}: {}", location, source),
+            Error::VersionNotFound { id } => write!(f, "version {} not found", id),
+            Error::NativeNotFound { library, classifier } => write!(
+                f, "library {} has no native classifier {}",
+                library, classifier
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Http { source: Some(source), .. } => Some(source.as_ref()),
+            Error::Json { source, .. } => Some(source),
+            Error::Xml { source, .. } => Some(source),
+            Error::Zip { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(source: reqwest::Error) -> Self {
+        Error::Http {
+            url: source.url().map(|u| u.to_string()).unwrap_or_default(),
+            status: source.status(),
+            source: Some(Box::new(source)),
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(source: serde_json::Error) -> Self {
+        Error::Json { location: None, source }
+    }
+}
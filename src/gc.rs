@@ -0,0 +1,84 @@
+use std::{collections::HashSet, io::Cursor, path::{Path, PathBuf}};
+use tokio::fs::remove_file;
+use zip::ZipArchive;
+
+use crate::{
+    context::{Context, LockKind},
+    error::{Error, Result},
+    version::{Assets, Native, Version}
+};
+
+pub(crate) const DIRS: [&str; 4] = ["libraries", "natives", "assets/objects", "assets/indexes"];
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub files: Vec<PathBuf>,
+    pub bytes: u64,
+    pub dry_run: bool
+}
+
+// Finds, and unless `dry_run` is set removes, every file under the directories
+// the version module manages that none of `versions` still reference.
+pub async fn collect(ctx: &Context, versions: &[Version], dry_run: bool) -> Result<Report> {
+    let _lock = ctx.lock(LockKind::Exclusive).await?;
+    let keep = referenced(ctx, versions).await?;
+    let mut report = Report { dry_run, ..Report::default() };
+    for dir in DIRS.iter() {
+        for (path, size) in ctx.walk(dir).await? {
+            if keep.contains(&path) { continue }
+            if !dry_run {
+                let full = ctx.resolve(&path)?;
+                remove_file(&full).await.map_err(|e| Error::io(&full, e))?;
+            }
+            report.files.push(path);
+            report.bytes += size;
+        }
+    }
+    Ok(report)
+}
+
+pub(crate) async fn referenced(ctx: &Context, versions: &[Version]) -> Result<HashSet<PathBuf>> {
+    let mut keep = HashSet::new();
+    for version in versions {
+        keep.extend(version.libraries.iter().map(|f| Path::new("libraries").join(&f.path)));
+        keep.extend(objects(&version.assets));
+        // An unreadable jar is for `verify` to report; it just keeps nothing
+        // here, and re-extracting it after a repair brings its files back.
+        for native in &version.natives {
+            let entries = native_entries(ctx, native).await.unwrap_or_default();
+            keep.extend(entries.into_iter().map(|(path, _)| path));
+        }
+        let index = version.assets.index_path();
+        if let Ok(bytes) = ctx.read_file(&index).await {
+            let assets: Assets = serde_json::from_slice(&bytes)
+                .map_err(|e| Error::json(ctx.path(&index).display(), e))?;
+            keep.extend(objects(&assets));
+        }
+        keep.insert(index);
+    }
+    Ok(keep)
+}
+
+// Objects whose hash can't name a file have nothing on disk to keep.
+fn objects(assets: &Assets) -> impl Iterator<Item = PathBuf> + '_ {
+    assets.objects.values().filter_map(|o| o.path().ok())
+}
+
+// Lists the files (and their sizes) that extracting `native` puts under natives/.
+pub(crate) async fn native_entries(ctx: &Context, native: &Native) -> Result<Vec<(PathBuf, u64)>> {
+    let path = native.jar();
+    let jar = match ctx.read_file(&path).await {
+        Ok(jar) => jar,
+        Err(_) => return Ok(Vec::new())
+    };
+    let location = ctx.path(&path);
+    let mut zip = ZipArchive::new(Cursor::new(jar)).map_err(|e| Error::zip(location.display(), e))?;
+    let mut entries = Vec::new();
+    for i in 0..zip.len() {
+        let file = zip.by_index(i).map_err(|e| Error::zip(location.display(), e))?;
+        if !file.is_dir() && !native.excludes(file.name()) {
+            entries.push((Path::new("natives").join(file.name()), file.size()));
+        }
+    }
+    Ok(entries)
+}
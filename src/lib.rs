@@ -1,5 +1,8 @@
 pub mod context;
+pub mod error;
+pub mod gc;
 pub mod manifest;
+pub mod storage;
 pub mod version;
 
 use std::collections::HashMap;
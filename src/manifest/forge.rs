@@ -1,9 +1,16 @@
-use anyhow::Result;
+use std::{collections::HashMap, fmt};
 use serde_derive::Deserialize;
 
-use crate::manifest::{Version, Manifest};
+use crate::{context::Context, error::{Error, Result}, manifest::{Manifest, Tag, Version}};
 
 const FORGE_MAVEN_URL: &str = "https://maven.minecraftforge.net/net/minecraftforge/forge";
+const PROMOTIONS_URL: &str = "https://files.minecraftforge.net/net/minecraftforge/forge/promotions_slim.json";
+
+#[derive(Debug, Deserialize)]
+struct Promotions {
+    // Keyed by `<minecraft>-latest` or `<minecraft>-recommended`.
+    promos: HashMap<String, String>
+}
 
 #[derive(Debug, Deserialize)]
 struct Root {
@@ -21,14 +28,59 @@ struct Versions {
     list: Vec<String>
 }
 
-pub async fn parse(url: &str) -> Result<Manifest> {
-    let text = reqwest::get(url).await?.text().await?;
-    let root: Root = serde_xml_rs::from_str(&text)?;
+// A Forge maven version such as `1.16.5-36.2.39`, or `1.7.10-10.13.4.1614-1.7.10`
+// for builds published on a branch.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct ForgeVersion {
+    pub minecraft: String,
+    pub forge: String,
+    pub branch: Option<String>
+}
+
+impl ForgeVersion {
+    // Also takes the installer's own ids, `1.16.5-forge-36.2.39` and the
+    // older `1.12.2-forge1.12.2-14.23.5.2855`. Anything whose Forge part
+    // isn't a dotted number, like a bare `36.2.39`, is rejected.
+    pub fn parse(id: &str) -> Option<Self> {
+        let (minecraft, rest) = id.split_once('-')?;
+        let rest = strip_prefix(rest, "forge")
+            .map(|r| strip_prefix(r, "-").unwrap_or(r))
+            .unwrap_or(rest);
+        let rest = rest.strip_prefix(&format!("{}-", minecraft)).unwrap_or(rest);
+        let (forge, branch) = match rest.split_once('-') {
+            Some((forge, branch)) => (forge, Some(branch.to_string())),
+            None => (rest, None)
+        };
+        let numeric = forge.starts_with(|c: char| c.is_ascii_digit())
+            && forge.chars().all(|c| c.is_ascii_digit() || c == '.');
+        if minecraft.is_empty() || !numeric { return None }
+        Some(Self { minecraft: minecraft.to_string(), forge: forge.to_string(), branch })
+    }
+}
+
+fn strip_prefix<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    if s.len() >= prefix.len() && s[..prefix.len()].eq_ignore_ascii_case(prefix) {
+        Some(&s[prefix.len()..])
+    } else { None }
+}
+
+impl fmt::Display for ForgeVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.minecraft, self.forge)?;
+        if let Some(branch) = &self.branch { write!(f, "-{}", branch)? }
+        Ok(())
+    }
+}
+
+pub async fn parse(ctx: &Context, url: &str) -> Result<Manifest> {
+    let bytes = ctx.get_cached(url).await?;
+    let root: Root = serde_xml_rs::from_reader(bytes.as_slice())
+        .map_err(|e| Error::Xml { location: url.to_string(), source: e })?;
     let versions = root.versioning.versions.list
         .iter()
         .map(|v| {
             Version {
-                id: v.split("-").last().unwrap().to_string(),
+                id: v.to_string(),
                 tags: Vec::new(),
                 url: format!(
                     "{}/{}/forge-{}-installer.jar",
@@ -39,6 +91,137 @@ pub async fn parse(url: &str) -> Result<Manifest> {
     Ok(Manifest { tags: Vec::new(), versions })
 }
 
-pub async fn get() -> Result<Manifest> {
-    parse(&format!("{}/maven-metadata.xml", FORGE_MAVEN_URL)).await
-}
\ No newline at end of file
+fn tags() -> [Tag; 2] {
+    [
+        Tag { id: "latest".to_string(), name: "Latest".to_string(), filter: false },
+        Tag { id: "recommended".to_string(), name: "Recommended".to_string(), filter: false }
+    ]
+}
+
+// Tags the builds promoted as latest or recommended for their Minecraft version.
+pub async fn promote(ctx: &Context, url: &str, manifest: &mut Manifest) -> Result<()> {
+    let bytes = ctx.get_cached(url).await?;
+    let promotions: Promotions = serde_json::from_slice(&bytes).map_err(|e| Error::json(url, e))?;
+    for tag in tags().iter() {
+        for v in &mut manifest.versions {
+            let promoted = ForgeVersion::parse(&v.id).map(|f| {
+                promotions.promos.get(&format!("{}-{}", f.minecraft, tag.id)) == Some(&f.forge)
+            });
+            if promoted == Some(true) { v.tags.push(tag.clone()) }
+        }
+        if !manifest.tags.contains(tag) { manifest.tags.push(tag.clone()) }
+    }
+    Ok(())
+}
+
+// The bare maven listing without promotion tags.
+pub async fn maven(ctx: &Context) -> Result<Manifest> {
+    parse(ctx, &format!("{}/maven-metadata.xml", FORGE_MAVEN_URL)).await
+}
+
+pub async fn get(ctx: &Context) -> Result<Manifest> {
+    let mut manifest = maven(ctx).await?;
+    // Tags are only decoration; an unreachable or uncached promotions file
+    // leaves the manifest untagged rather than failing it.
+    let _ = promote(ctx, PROMOTIONS_URL, &mut manifest).await;
+    Ok(manifest)
+}
+
+// Every Forge build for the given Minecraft version, newest first as listed.
+pub fn builds<'a>(manifest: &'a Manifest, minecraft: &'a str) -> impl Iterator<Item = &'a Version> {
+    manifest.versions.iter().filter(move |v| {
+        matches!(ForgeVersion::parse(&v.id), Some(f) if f.minecraft == minecraft)
+    })
+}
+
+// Looks up a build by its maven version, the installer's id for it, or the
+// bare Forge version.
+pub fn find<'a>(manifest: &'a Manifest, id: &str) -> Option<&'a Version> {
+    manifest.get(id)
+        .or_else(|| ForgeVersion::parse(id).and_then(|f| manifest.get(&f.to_string())))
+        .or_else(|| {
+            manifest.versions.iter().find(|v| {
+                matches!(ForgeVersion::parse(&v.id), Some(f) if f.forge == id)
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forge(minecraft: &str, forge: &str, branch: Option<&str>) -> ForgeVersion {
+        ForgeVersion {
+            minecraft: minecraft.to_string(),
+            forge: forge.to_string(),
+            branch: branch.map(String::from)
+        }
+    }
+
+    fn manifest(ids: &[&str]) -> Manifest {
+        let versions = ids
+            .iter()
+            .map(|id| Version { id: id.to_string(), url: String::new(), tags: Vec::new() })
+            .collect();
+        Manifest { tags: Vec::new(), versions }
+    }
+
+    #[test]
+    fn parses_maven_versions() {
+        assert_eq!(ForgeVersion::parse("1.16.5-36.2.39"), Some(forge("1.16.5", "36.2.39", None)));
+        assert_eq!(
+            ForgeVersion::parse("1.7.10-10.13.4.1614-1.7.10"),
+            Some(forge("1.7.10", "10.13.4.1614", Some("1.7.10")))
+        );
+    }
+
+    #[test]
+    fn parses_installer_ids() {
+        assert_eq!(ForgeVersion::parse("1.16.5-forge-36.2.39"), Some(forge("1.16.5", "36.2.39", None)));
+        assert_eq!(
+            ForgeVersion::parse("1.12.2-forge1.12.2-14.23.5.2855"),
+            Some(forge("1.12.2", "14.23.5.2855", None))
+        );
+        assert_eq!(
+            ForgeVersion::parse("1.7.10-Forge10.13.4.1614-1.7.10"),
+            Some(forge("1.7.10", "10.13.4.1614", Some("1.7.10")))
+        );
+    }
+
+    #[test]
+    fn rejects_non_forge_ids() {
+        assert_eq!(ForgeVersion::parse("36.2.39"), None);
+        assert_eq!(ForgeVersion::parse("1.16.5-"), None);
+        assert_eq!(ForgeVersion::parse("-36.2.39"), None);
+        assert_eq!(ForgeVersion::parse("1.16.5-forge"), None);
+        assert_eq!(ForgeVersion::parse("1.16.5-beta-1"), None);
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for id in ["1.16.5-36.2.39", "1.7.10-10.13.4.1614-1.7.10"].iter() {
+            let parsed = ForgeVersion::parse(id).unwrap();
+            assert_eq!(parsed.to_string(), *id);
+            assert_eq!(ForgeVersion::parse(&parsed.to_string()), Some(parsed));
+        }
+    }
+
+    #[test]
+    fn finds_by_any_id_form() {
+        let manifest = manifest(&["1.16.5-36.2.39", "1.16.4-35.1.37", "1.7.10-10.13.4.1614-1.7.10"]);
+        let found = |id| find(&manifest, id).map(|v| v.id.as_str());
+        assert_eq!(found("1.16.5-36.2.39"), Some("1.16.5-36.2.39"));
+        assert_eq!(found("1.16.5-forge-36.2.39"), Some("1.16.5-36.2.39"));
+        assert_eq!(found("35.1.37"), Some("1.16.4-35.1.37"));
+        assert_eq!(found("10.13.4.1614"), Some("1.7.10-10.13.4.1614-1.7.10"));
+        assert_eq!(found("1.16.5-36.2.40"), None);
+    }
+
+    #[test]
+    fn lists_builds_for_a_minecraft_version() {
+        let manifest = manifest(&["1.16.5-36.2.39", "1.16.4-35.1.37", "1.16.5-36.2.34"]);
+        let ids: Vec<&str> = builds(&manifest, "1.16.5").map(|v| v.id.as_str()).collect();
+        assert_eq!(ids, vec!["1.16.5-36.2.39", "1.16.5-36.2.34"]);
+        assert_eq!(builds(&manifest, "1.12.2").count(), 0);
+    }
+}
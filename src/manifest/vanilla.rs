@@ -1,7 +1,7 @@
 use std::collections::HashSet;
-use anyhow::Result;
 use serde_derive::Deserialize;
 
+use crate::{context::Context, error::{Error, Result}};
 use super::*;
 
 const MANIFEST_URL: &str = "https://launchermeta.mojang.com/mc/game/version_manifest.json";
@@ -26,8 +26,9 @@ struct Version {
     _type: String
 }
 
-pub async fn parse(url: &str) -> Result<Manifest> {
-    let root: Root = reqwest::get(url).await?.json().await?;
+pub async fn parse(ctx: &Context, url: &str) -> Result<Manifest> {
+    let bytes = ctx.get_cached(url).await?;
+    let root: Root = serde_json::from_slice(&bytes).map_err(|e| Error::json(url, e))?;
     let mut tags = HashSet::new();
     let latest_tag = Tag {
         id: "latest".to_string(),
@@ -64,6 +65,6 @@ pub async fn parse(url: &str) -> Result<Manifest> {
     Ok(Manifest { tags, versions })
 }
 
-pub async fn get() -> Result<Manifest> {
-    parse(MANIFEST_URL).await
+pub async fn get(ctx: &Context) -> Result<Manifest> {
+    parse(ctx, MANIFEST_URL).await
 }
\ No newline at end of file
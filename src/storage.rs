@@ -0,0 +1,73 @@
+use std::{collections::{BTreeMap, HashMap}, slice};
+
+use crate::{
+    context::{Context, LockKind},
+    error::Result,
+    gc::{referenced, DIRS},
+    version::Version
+};
+
+// Directories outside the version module's files that still take up room.
+const OTHER_DIRS: [&str; 2] = ["versions", "cache"];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionUsage {
+    // Bytes this version references, whether or not others do too.
+    pub total: u64,
+    // Bytes only this version references; what removing it would free.
+    pub exclusive: u64
+}
+
+#[derive(Debug, Default)]
+pub struct Usage {
+    pub dirs: BTreeMap<String, u64>,
+    pub versions: BTreeMap<String, VersionUsage>,
+    // Bytes referenced by more than one of the versions.
+    pub shared: u64,
+    // Bytes under the managed directories that none of the versions reference.
+    pub unreferenced: u64,
+    pub total: u64
+}
+
+// Walks the context root and attributes each managed file to the versions
+// that reference it.
+pub async fn report(ctx: &Context, versions: &[Version]) -> Result<Usage> {
+    let _lock = ctx.lock(LockKind::Shared).await?;
+    let mut refs = Vec::new();
+    for version in versions {
+        refs.push((version.id.as_str(), referenced(ctx, slice::from_ref(version)).await?));
+    }
+    let mut usage = Usage::default();
+    for dir in OTHER_DIRS.iter() {
+        let bytes = ctx.walk(dir).await?.into_iter().map(|(_, size)| size).sum();
+        usage.dirs.insert(dir.to_string(), bytes);
+        usage.total += bytes;
+    }
+    let mut owners: HashMap<&str, VersionUsage> = HashMap::new();
+    for dir in DIRS.iter() {
+        let mut bytes = 0;
+        for (path, size) in ctx.walk(dir).await? {
+            bytes += size;
+            let users: Vec<&str> = refs
+                .iter()
+                .filter(|(_, keep)| keep.contains(&path))
+                .map(|(id, _)| *id)
+                .collect();
+            match users.len() {
+                0 => usage.unreferenced += size,
+                1 => owners.entry(users[0]).or_default().exclusive += size,
+                _ => usage.shared += size
+            }
+            for id in users {
+                owners.entry(id).or_default().total += size;
+            }
+        }
+        usage.dirs.insert(dir.to_string(), bytes);
+        usage.total += bytes;
+    }
+    usage.versions = versions
+        .iter()
+        .map(|v| (v.id.clone(), owners.remove(v.id.as_str()).unwrap_or_default()))
+        .collect();
+    Ok(usage)
+}
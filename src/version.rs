@@ -1,24 +1,53 @@
+mod archive;
 pub mod forge;
+mod plan;
 mod structs;
 pub mod vanilla;
+mod verify;
 
-use anyhow::Result;
-use futures::{stream, StreamExt};
+use futures::{future, stream, StreamExt};
 use maplit::hashmap;
 use serde_derive::*;
 use std::os::windows::process::CommandExt;
 use std::process::Command;
-use std::{collections::HashMap, io::Cursor};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+    sync::Arc
+};
 use zip::ZipArchive;
 
-use crate::{context::Context, format};
+use crate::{
+    context::{self, progress::Tracker, CancelToken, Context, LockKind, Phase, RootLock},
+    error::{Error, Result},
+    format
+};
 
-#[derive(Debug, Deserialize, Serialize)]
+pub use plan::{Download, Plan};
+pub use verify::{Problem, Verification};
+
+const RESOURCES_URL: &str = "http://resources.download.minecraft.net";
+
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
 pub struct Object {
     pub hash: String,
     pub size: u32,
 }
 
+impl Object {
+    pub fn path(&self) -> Result<PathBuf> {
+        context::check_sha1(&self.hash)?;
+        Ok(Path::new("assets/objects").join(&self.hash[0..2]).join(&self.hash))
+    }
+
+    pub fn url(&self) -> Result<String> {
+        context::check_sha1(&self.hash)?;
+        Ok(format!("{}/{}/{}", RESOURCES_URL, &self.hash[0..2], self.hash))
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Assets {
     #[serde(default)]
@@ -26,11 +55,69 @@ pub struct Assets {
     pub objects: HashMap<String, Object>,
 }
 
+impl Assets {
+    pub fn index_path(&self) -> PathBuf {
+        Path::new("assets/indexes").join(format!("{}.json", self.id))
+    }
+
+    // Many names point at the same blob, so this yields each hash only once.
+    pub fn unique_objects(&self) -> Vec<&Object> {
+        let mut seen = HashSet::new();
+        self.objects.values().filter(|o| seen.insert(&o.hash)).collect()
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct File {
     pub url: String,
-    pub path: String,
+    pub path: PathBuf,
     pub size: u32,
+    #[serde(default)]
+    pub sha1: String,
+    // Names the file inside the archive at `url` (a Forge installer) when
+    // it's unpacked from there rather than downloaded directly.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry: Option<String>,
+}
+
+// A jar of native libraries and the entry prefixes (usually `META-INF/`)
+// that are left out when it's extracted.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(from = "NativeRepr")]
+pub struct Native {
+    pub path: PathBuf,
+    pub exclude: Vec<String>,
+}
+
+// Versions persisted before excludes were kept list natives as bare paths.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NativeRepr {
+    Path(PathBuf),
+    Native {
+        path: PathBuf,
+        #[serde(default)]
+        exclude: Vec<String>
+    }
+}
+
+impl From<NativeRepr> for Native {
+    fn from(repr: NativeRepr) -> Self {
+        match repr {
+            NativeRepr::Path(path) => Native { path, exclude: Vec::new() },
+            NativeRepr::Native { path, exclude } => Native { path, exclude }
+        }
+    }
+}
+
+impl Native {
+    pub fn jar(&self) -> PathBuf {
+        Path::new("libraries").join(&self.path)
+    }
+
+    pub fn excludes(&self, name: &str) -> bool {
+        self.exclude.iter().any(|e| name.starts_with(e.as_str()))
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -40,8 +127,76 @@ pub struct Version {
     pub game_args: Vec<String>,
     pub jvm_args: Vec<String>,
     pub libraries: Vec<File>,
-    pub natives: Vec<String>,
+    pub natives: Vec<Native>,
     pub main_class: String,
+    // Where the resolved JSON is persisted under the context root, which for
+    // Forge is keyed by the maven version rather than `id`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct Failure {
+    pub path: PathBuf,
+    pub error: Error,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub failed: Vec<Failure>,
+    pub cancelled: bool,
+}
+
+impl Report {
+    // Files that were cut short by `cancel` are not failures of their own;
+    // the report is just marked cancelled instead.
+    fn new(failed: Vec<Failure>, cancel: &CancelToken) -> Self {
+        Self {
+            failed: failed.into_iter().filter(|f| !matches!(f.error, Error::Cancelled)).collect(),
+            cancelled: cancel.is_cancelled()
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty() && !self.cancelled
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    pub fn merge(&mut self, other: Report) {
+        self.failed.extend(other.failed);
+        self.cancelled |= other.cancelled;
+    }
+}
+
+// Unpacks `native` into natives/, skipping excluded entries and rejecting
+// any that would land outside it.
+async fn extract(ctx: &Context, native: &Native) -> Result<()> {
+    let jar = native.jar();
+    let location = ctx.path(&jar);
+    let mut zip = ZipArchive::new(Cursor::new(ctx.read_file(&jar).await?))
+        .map_err(|e| Error::zip(location.display(), e))?;
+    for i in 0..zip.len() {
+        let (path, bytes) = {
+            let mut file = zip.by_index(i).map_err(|e| Error::zip(location.display(), e))?;
+            if file.is_dir() || native.excludes(file.name()) { continue }
+            let mut bytes = Vec::new();
+            file.read_to_end(&mut bytes).map_err(|e| Error::zip(location.display(), e.into()))?;
+            (Path::new("natives").join(file.name()), bytes)
+        };
+        ctx.write_file(path, &bytes).await?;
+    }
+    Ok(())
+}
+
+// Takes the root exclusively for an install, reporting a failure to do so.
+async fn lock(ctx: &Context) -> std::result::Result<Arc<RootLock>, Report> {
+    ctx.lock(LockKind::Exclusive).await.map_err(|error| Report {
+        failed: vec![Failure { path: ctx.path(".lock"), error }],
+        ..Report::default()
+    })
 }
 
 impl Version {
@@ -53,83 +208,140 @@ impl Version {
         Ok(serde_json::to_vec(self)?)
     }
 
+    // Resolves a version through `fetch` and persists it at `path`, so the
+    // same id can be resolved again from the context root while offline.
+    pub(crate) async fn resolve<P, F, Fut>(ctx: &Context, path: P, fetch: F) -> Result<Version>
+    where
+        P: AsRef<Path>,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Version>>
+    {
+        let path = path.as_ref();
+        let bytes = ctx.cached(path, || async {
+            let mut version = fetch().await?;
+            version.resolved = Some(path.to_path_buf());
+            let bytes = version.to_vec()?;
+            ctx.write_file(path, &bytes).await?;
+            Ok(bytes)
+        }).await?;
+        let mut version: Version = serde_json::from_slice(&bytes)
+            .map_err(|e| Error::json(ctx.path(path).display(), e))?;
+        version.resolved = Some(path.to_path_buf());
+        Ok(version)
+    }
+
     pub fn classpath(&self, ctx: &Context) -> String {
         let libs: Vec<String> = self
             .libraries
             .iter()
-            .map(|f| ctx.path(&format!("libraries/{}", f.path)))
+            .map(|f| ctx.path("libraries").join(&f.path).to_string_lossy().into_owned())
             .collect();
         libs.join(";")
     }
 
-    pub async fn ensure_libraries(&self, ctx: &Context) {
-        stream::iter(&self.libraries)
-            .for_each_concurrent(ctx.max_concurrent as usize, |f| async move {
-                let path = format!("libraries/{}", f.path);
-                match ctx.check_file_size(&path, f.size as u64).await {
-                    Ok(_) => {}
-                    Err(_) => {
-                        let bytes = ctx.get(&f.url).await.unwrap().bytes().await.unwrap();
-                        ctx.write_file(&path, &bytes).await.unwrap();
-                    }
-                }
-            })
-            .await;
+    pub async fn ensure_libraries(&self, ctx: &Context, cancel: &CancelToken) -> Report {
+        let lock = match lock(ctx).await { Ok(lock) => lock, Err(report) => return report };
+        self.install_libraries(ctx, &lock, cancel).await
     }
 
-    pub async fn ensure_natives(&self, ctx: &Context) {
-        stream::iter(&self.natives)
-            .for_each_concurrent(ctx.max_concurrent as usize, |n| async move {
-                let jar = ctx.read_file(&format!("libraries/{}", n)).await.unwrap();
-                ZipArchive::new(Cursor::new(jar))
-                    .unwrap()
-                    .extract(&ctx.path("natives"))
-                    .unwrap();
-            })
-            .await;
+    pub async fn ensure_natives(&self, ctx: &Context, cancel: &CancelToken) -> Report {
+        let lock = match lock(ctx).await { Ok(lock) => lock, Err(report) => return report };
+        self.install_natives(ctx, &lock, cancel).await
+    }
+
+    pub async fn ensure_assets(&self, ctx: &Context, cancel: &CancelToken) -> Report {
+        let lock = match lock(ctx).await { Ok(lock) => lock, Err(report) => return report };
+        self.install_assets(ctx, &lock, cancel).await
+    }
+
+    pub async fn write_index(&self, ctx: &Context) -> Result<()> {
+        ctx.write_file(self.assets.index_path(), &serde_json::to_vec(&self.assets)?).await
     }
 
-    pub async fn ensure_assets(&self, ctx: &Context) {
-        stream::iter(&self.assets.objects)
-            .for_each_concurrent(16, |(_, o)| async move {
-                let path1 = format!("{}/{}", &o.hash[0..2], o.hash);
-                let path2 = format!("assets/objects/{}", path1);
-                match ctx.check_file_size(&path2, o.size as u64).await {
-                    Ok(_) => {}
-                    Err(_) => {
-                        let bytes = ctx
-                            .get(&format!(
-                                "http://resources.download.minecraft.net/{}",
-                                path1
-                            ))
-                            .await
-                            .unwrap()
-                            .bytes()
-                            .await
-                            .unwrap();
-                        ctx.write_file(&path2, &bytes).await.unwrap();
-                    }
-                };
+    pub async fn ensure_all(&self, ctx: &Context, cancel: &CancelToken) -> Report {
+        let lock = match lock(ctx).await { Ok(lock) => lock, Err(report) => return report };
+        self.install_all(ctx, &lock, cancel).await
+    }
+
+    // Installs several versions at once through the context's scheduler.
+    pub async fn ensure_many(versions: &[&Version], ctx: &Context, cancel: &CancelToken) -> Report {
+        let lock = match lock(ctx).await { Ok(lock) => lock, Err(report) => return report };
+        let lock = &lock;
+        let reports = future::join_all(versions.iter().map(|v| v.install_all(ctx, lock, cancel))).await;
+        reports.into_iter().fold(Report::default(), |mut report, r| {
+            report.merge(r);
+            report
+        })
+    }
+
+    // The `install_*` steps do the work of their `ensure_*` counterparts for
+    // callers that already hold the root exclusively.
+    pub(super) async fn install_libraries(&self, ctx: &Context, _lock: &RootLock, cancel: &CancelToken) -> Report {
+        plan::fetch(ctx, Phase::Libraries, &self.library_downloads(), cancel).await
+    }
+
+    pub(super) async fn install_natives(&self, ctx: &Context, _lock: &RootLock, cancel: &CancelToken) -> Report {
+        let size = |n: &Native| self.libraries
+            .iter()
+            .find(|f| f.path == n.path)
+            .map_or(0, |f| f.size as u64);
+        let total_bytes = self.natives.iter().map(size).sum();
+        let tracker = Tracker::new(ctx, Phase::Natives, self.natives.len(), total_bytes);
+        let tracker = &tracker;
+        let failed: Vec<Failure> = stream::iter(&self.natives)
+            .map(|n| async move {
+                cancel.check().ok()?;
+                let path = n.jar();
+                tracker.start(&path, size(n));
+                let result = extract(ctx, n).await;
+                tracker.finish(&path, size(n), result.is_ok());
+                result.err().map(|error| Failure { path, error })
             })
+            .buffer_unordered(ctx.settings.library_concurrency)
+            .filter_map(future::ready)
+            .collect()
             .await;
-        ctx.write_file(
-            &format!("assets/indexes/{}.json", self.assets.id),
-            &serde_json::to_vec(&self.assets).unwrap(),
-        )
-        .await
-        .unwrap();
+        let report = Report::new(failed, cancel);
+        tracker.done(report.failed.len());
+        report
     }
 
-    pub async fn ensure_all(&self, ctx: &Context) {
-        self.ensure_libraries(ctx).await;
-        self.ensure_natives(ctx).await;
-        self.ensure_assets(ctx).await;
+    pub(super) async fn install_assets(&self, ctx: &Context, _lock: &RootLock, cancel: &CancelToken) -> Report {
+        let downloads = match self.asset_downloads() {
+            Ok(downloads) => downloads,
+            Err(error) => {
+                let failed = vec![Failure { path: self.assets.index_path(), error }];
+                return Report { failed, ..Report::default() }
+            }
+        };
+        let mut report = plan::fetch(ctx, Phase::Assets, &downloads, cancel).await;
+        if !report.cancelled {
+            if let Err(error) = self.write_index(ctx).await {
+                report.failed.push(Failure { path: self.assets.index_path(), error });
+            }
+        }
+        report
     }
 
-    pub async fn launch(&self, ctx: &Context, args: Vec<String>, vars: HashMap<&str, &str>) {
+    // Assets download alongside libraries rather than after them; the shared
+    // scheduler still lets libraries through first. Natives only wait on the
+    // libraries they're extracted from.
+    async fn install_all(&self, ctx: &Context, lock: &RootLock, cancel: &CancelToken) -> Report {
+        let libraries = async {
+            let mut report = self.install_libraries(ctx, lock, cancel).await;
+            report.merge(self.install_natives(ctx, lock, cancel).await);
+            report
+        };
+        let (mut report, assets) = future::join(libraries, self.install_assets(ctx, lock, cancel)).await;
+        report.merge(assets);
+        report
+    }
+
+    pub async fn launch(&self, ctx: &Context, args: Vec<String>, vars: HashMap<&str, &str>) -> Result<()> {
+        let _lock = ctx.lock(LockKind::Shared).await?;
         let mut variables = hashmap! {
-            "natives_directory" => ctx.path("natives"),
-            "assets_root" => ctx.path("assets"),
+            "natives_directory" => ctx.path("natives").to_string_lossy().into_owned(),
+            "assets_root" => ctx.path("assets").to_string_lossy().into_owned(),
             "assets_index_name" => self.assets.id.clone(),
             "classpath" => self.classpath(ctx)
         };
@@ -153,7 +365,35 @@ impl Version {
             .args(args)
             .args(game_args)
             .output()
-            .unwrap();
+            .map_err(|e| Error::io("java", e))?;
         println!("{}", String::from_utf8_lossy(&output.stderr));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(hash: &str) -> Object {
+        Object { hash: hash.to_string(), size: 0 }
+    }
+
+    #[test]
+    fn objects_are_laid_out_by_hash_prefix() {
+        let o = object("bdf48ef6b5d0d23bbb02e17d04865216179f510a");
+        assert_eq!(o.path().unwrap(), Path::new("assets/objects/bd/bdf48ef6b5d0d23bbb02e17d04865216179f510a"));
+        assert_eq!(
+            o.url().unwrap(),
+            "http://resources.download.minecraft.net/bd/bdf48ef6b5d0d23bbb02e17d04865216179f510a"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_hashes() {
+        for hash in ["", "b", "é", "../../../../etc/passwd", "zzf48ef6b5d0d23bbb02e17d04865216179f510a"].iter() {
+            assert!(matches!(object(hash).path(), Err(Error::InvalidPath { .. })), "{}", hash);
+            assert!(object(hash).url().is_err(), "{}", hash);
+        }
     }
 }
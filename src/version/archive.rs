@@ -0,0 +1,125 @@
+use std::{fs, io::{self, Read, Write}, path::{Path, PathBuf}};
+use serde_derive::{Deserialize, Serialize};
+use tokio::{sync::mpsc, task::{spawn_blocking, JoinError}};
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{
+    context::{self, CancelToken, Context, LockKind},
+    error::{Error, Result}
+};
+use super::{Download, Plan, Version};
+
+const MANIFEST: &str = "manifest.json";
+
+// Generic so exporting can borrow the version it serializes.
+#[derive(Debug, Deserialize, Serialize)]
+struct Manifest<V> {
+    version: V,
+    files: Vec<Download>
+}
+
+// Zip entry names always use forward slashes, whatever the platform.
+fn entry_name(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn join_error(path: &Path, e: JoinError) -> Error {
+    Error::io(path, io::Error::other(e))
+}
+
+impl Version {
+    // Packs this version into a zip at `dest`, which is a plain filesystem
+    // path rather than one under the context. With `missing` (a plan made
+    // against the target context) only the files it lists are included.
+    pub async fn export<P: AsRef<Path>>(&self, ctx: &Context, dest: P, missing: Option<&Plan>) -> Result<()> {
+        let dest = dest.as_ref().to_path_buf();
+        let _lock = ctx.lock(LockKind::Shared).await?;
+        let files = match missing {
+            Some(plan) => plan.downloads.clone(),
+            None => self.downloads()?
+        };
+        let sources = files
+            .iter()
+            .map(|f| Ok((entry_name(&f.path), ctx.resolve(&f.path)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let manifest = serde_json::to_vec(&Manifest { version: self, files })?;
+        let path = dest.clone();
+        spawn_blocking(move || pack(&path, sources, manifest))
+            .await
+            .map_err(|e| join_error(&dest, e))?
+    }
+
+    // Unpacks an archive made by `export` into `ctx`, checking every file
+    // against its size and SHA-1 before it's written, then extracts natives
+    // and writes the asset index and the resolved version JSON.
+    pub async fn import<P: AsRef<Path>>(ctx: &Context, src: P) -> Result<Version> {
+        let src = src.as_ref().to_path_buf();
+        let lock = ctx.lock(LockKind::Exclusive).await?;
+        // Decompressing and hashing happen on a blocking thread; only the
+        // verified files come back here to be written.
+        let (tx, mut rx) = mpsc::channel(4);
+        let path = src.clone();
+        let reader = spawn_blocking(move || unpack(&path, tx));
+        while let Some((path, bytes)) = rx.recv().await {
+            ctx.write_file(&path, &bytes).await?;
+        }
+        let version = reader.await.map_err(|e| join_error(&src, e))??;
+        let report = version.install_natives(ctx, &lock, &CancelToken::new()).await;
+        if let Some(failure) = report.failed.into_iter().next() {
+            return Err(failure.error)
+        }
+        version.write_index(ctx).await?;
+        if let Some(path) = &version.resolved {
+            ctx.write_file(path, &version.to_vec()?).await?;
+        }
+        Ok(version)
+    }
+}
+
+fn pack(dest: &Path, sources: Vec<(String, PathBuf)>, manifest: Vec<u8>) -> Result<()> {
+    let zip_err = |e| Error::zip(dest.display(), e);
+    let io_err = |e| Error::io(dest, e);
+    let mut zip = ZipWriter::new(fs::File::create(dest).map_err(io_err)?);
+    // Jars and asset objects are already compressed.
+    let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+    for (name, path) in sources {
+        let mut file = fs::File::open(&path).map_err(|e| Error::io(&path, e))?;
+        zip.start_file(name, options).map_err(zip_err)?;
+        io::copy(&mut file, &mut zip).map_err(|e| Error::io(&path, e))?;
+    }
+    zip.start_file(MANIFEST, options).map_err(zip_err)?;
+    zip.write_all(&manifest).map_err(io_err)?;
+    zip.finish().map_err(zip_err)?;
+    Ok(())
+}
+
+fn unpack(src: &Path, tx: mpsc::Sender<(PathBuf, Vec<u8>)>) -> Result<Version> {
+    let zip_err = |e| Error::zip(src.display(), e);
+    let file = fs::File::open(src).map_err(|e| Error::io(src, e))?;
+    let mut zip = ZipArchive::new(file).map_err(zip_err)?;
+    let mut read = |name: &str| -> Result<Vec<u8>> {
+        let mut entry = zip.by_name(name).map_err(zip_err)?;
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes).map_err(|e| zip_err(e.into()))?;
+        Ok(bytes)
+    };
+    let manifest: Manifest<Version> = serde_json::from_slice(&read(MANIFEST)?)
+        .map_err(|e| Error::json(format!("{}!/{}", src.display(), MANIFEST), e))?;
+    for f in manifest.files {
+        let bytes = read(&entry_name(&f.path))?;
+        if bytes.len() as u64 != f.size {
+            let actual = bytes.len() as u64;
+            return Err(Error::SizeMismatch { path: f.path, expected: f.size, actual })
+        }
+        let actual = context::sha1(&bytes);
+        if !f.sha1.is_empty() && !actual.eq_ignore_ascii_case(&f.sha1) {
+            return Err(Error::HashMismatch { path: f.path, expected: f.sha1, actual })
+        }
+        // The receiver only goes away once writing has already failed.
+        if tx.blocking_send((f.path, bytes)).is_err() { break }
+    }
+    Ok(manifest.version)
+}
@@ -1,10 +1,9 @@
-use std::io::{Cursor, Read};
+use std::{io::{Cursor, Read}, path::PathBuf};
 
-use anyhow::{Result, anyhow};
 use serde_derive::Deserialize;
 use zip::ZipArchive;
 
-use crate::{manifest, version::{
+use crate::{context::{self, CancelToken, Context}, error::{Error, Result}, manifest, version::{
     self, File, Version,
     structs:: {
         library::Library,
@@ -27,13 +26,17 @@ struct Root {
     libraries: Vec<Library>
 }
 
-pub async fn parse(url: &str) -> Result<Version> {
-    let inst = reqwest::get(url).await?.bytes().await?;
-    let mut zip = ZipArchive::new(Cursor::new(inst))?;
+pub async fn parse(ctx: &Context, url: &str, cancel: &CancelToken) -> Result<Version> {
+    let inst = cancel.run(async { ctx.get(url).await?.bytes().await }).await?;
+    let mut zip = ZipArchive::new(Cursor::new(inst)).map_err(|e| Error::zip(url, e))?;
     let mut file = Vec::new();
-    zip.by_name("version.json")?.read_to_end(&mut file)?;
-    let root: Root = serde_json::from_slice(&file)?;
-    let parent = version::vanilla::get(&root.inherits_from).await?;
+    zip.by_name("version.json")
+        .map_err(|e| Error::zip(url, e))?
+        .read_to_end(&mut file)
+        .map_err(|e| Error::zip(url, e.into()))?;
+    let root: Root = serde_json::from_slice(&file)
+        .map_err(|e| Error::json(format!("{}!/version.json", url), e))?;
+    let parent = version::vanilla::get(ctx, &root.inherits_from).await?;
     let mut game_args = Vec::new();
     let mut jvm_args = Vec::new();
     if root.minecraft_arguments.len() > 0 {
@@ -90,39 +93,77 @@ pub async fn parse(url: &str) -> Result<Version> {
     let mut libraries = parent.libraries;
     let split = root.id.split("-");
     let forge_id = format!("{}-{}", parent.id, split.last().unwrap());
-    root.libraries.into_iter().for_each(|l| {
-        if l.downloads.artifact.url.len() > 0 {
+    for l in root.libraries {
+        let artifact = l.downloads.artifact;
+        let path = PathBuf::from(artifact.path);
+        if !artifact.url.is_empty() {
             libraries.push(File {
-                path: l.downloads.artifact.path,
-                url: l.downloads.artifact.url,
-                size: l.downloads.artifact.size
+                path, url: artifact.url, size: artifact.size, sha1: artifact.sha1, entry: None
             });
-        } else {
-            libraries.push(File {
-                path:  l.downloads.artifact.path,
-                url: format!(
+            continue
+        }
+        // Forge's own jar isn't on the maven under this name; the installer
+        // ships it under maven/, so installs unpack it from there. Nothing is
+        // written here: that happens under the root lock like any download.
+        let entry = format!("maven/{}", path.to_string_lossy().replace('\\', "/"));
+        let file = match zip.by_name(&entry) {
+            Ok(mut file) => {
+                let mut bytes = Vec::new();
+                file.read_to_end(&mut bytes).map_err(|e| Error::zip(url, e.into()))?;
+                let actual = context::sha1(&bytes);
+                if !artifact.sha1.is_empty() && !actual.eq_ignore_ascii_case(&artifact.sha1) {
+                    return Err(Error::HashMismatch {
+                        path: PathBuf::from(format!("{}!/{}", url, entry)),
+                        expected: artifact.sha1,
+                        actual
+                    })
+                }
+                File { path, url: url.to_string(), size: bytes.len() as u32, sha1: actual, entry: Some(entry) }
+            }
+            // Older installers don't carry it, so take the universal jar,
+            // whose size and hash the JSON doesn't describe and have to be
+            // measured from a copy of it.
+            Err(_) => {
+                let universal = format!(
                     "{}/{}/forge-{}-universal.jar",
                     FORGE_MAVEN_URL, forge_id, forge_id
-                ),
-                size: l.downloads.artifact.size
-            })
-        }
-    });
+                );
+                let bytes = cancel.run(async { ctx.get(&universal).await?.bytes().await }).await?;
+                File { path, url: universal, size: bytes.len() as u32, sha1: context::sha1(&bytes), entry: None }
+            }
+        };
+        libraries.push(file);
+    }
     Ok(
         Version {
             id: root.id,
             natives: parent.natives,
             assets: parent.assets,
             main_class: root.main_class,
+            resolved: None,
             jvm_args, game_args, libraries
         }
     )
 }
 
-pub async fn get(id: &str) -> Result<Version> {
-    let manifest = manifest::forge::get().await?;
-    match manifest.get(id) {
-        Some(v) => { Ok(parse(&v.url).await?) }
-        None => { Err(anyhow!("")) }
-    }
-}
\ No newline at end of file
+// Accepts either the full maven version (`1.16.5-36.2.39`) or the bare Forge
+// version (`36.2.39`); the latter needs the manifest to find its full form.
+pub async fn get(ctx: &Context, id: &str, cancel: &CancelToken) -> Result<Version> {
+    let id = match manifest::forge::ForgeVersion::parse(id) {
+        Some(forge) => forge.to_string(),
+        None => {
+            let manifest = manifest::forge::maven(ctx).await?;
+            match manifest::forge::find(&manifest, id) {
+                Some(v) => v.id.clone(),
+                None => return Err(Error::VersionNotFound { id: id.to_string() })
+            }
+        }
+    };
+    Version::resolve(ctx, &format!("versions/forge/{}.json", id), || async {
+        let manifest = manifest::forge::maven(ctx).await?;
+        match manifest.get(&id) {
+            Some(v) => { Ok(parse(ctx, &v.url, cancel).await?) }
+            None => { Err(Error::VersionNotFound { id: id.clone() }) }
+        }
+    }).await
+}
@@ -0,0 +1,153 @@
+use std::path::{Path, PathBuf};
+use futures::{future, stream, StreamExt};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    context::{progress::Tracker, CancelToken, Context, LockKind, Phase},
+    error::{Error, Result}
+};
+use super::{lock, Failure, Report, Version};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Download {
+    pub phase: Phase,
+    pub url: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha1: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry: Option<String>,
+}
+
+// The files an install still has to fetch, worked out without downloading.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Plan {
+    pub downloads: Vec<Download>,
+    pub bytes: u64,
+}
+
+impl Plan {
+    pub fn files(&self) -> usize {
+        self.downloads.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.downloads.is_empty()
+    }
+
+    pub fn check_space(&self, ctx: &Context) -> Result<()> {
+        let available = ctx.available_space()?;
+        if available >= self.bytes { Ok(()) }
+        else {
+            Err(Error::InsufficientSpace { path: ctx.path.clone(), needed: self.bytes, available })
+        }
+    }
+}
+
+impl Version {
+    pub(super) fn library_downloads(&self) -> Vec<Download> {
+        self.libraries.iter().map(|f| Download {
+            phase: Phase::Libraries,
+            url: f.url.clone(),
+            path: Path::new("libraries").join(&f.path),
+            size: f.size as u64,
+            sha1: f.sha1.clone(),
+            entry: f.entry.clone()
+        }).collect()
+    }
+
+    // Fails on the first object whose hash can't name a file, since the
+    // index it came from can't be trusted.
+    pub(super) fn asset_downloads(&self) -> Result<Vec<Download>> {
+        self.assets.unique_objects().into_iter().map(|o| Ok(Download {
+            phase: Phase::Assets,
+            url: o.url()?,
+            path: o.path()?,
+            size: o.size as u64,
+            sha1: o.hash.clone(),
+            entry: None
+        })).collect()
+    }
+
+    // Every file the version expects, with what it takes to fetch it again.
+    pub(super) fn downloads(&self) -> Result<Vec<Download>> {
+        let mut downloads = self.library_downloads();
+        downloads.extend(self.asset_downloads()?);
+        Ok(downloads)
+    }
+
+    pub async fn plan(&self, ctx: &Context) -> Result<Plan> {
+        let _lock = ctx.lock(LockKind::Shared).await?;
+        let downloads: Vec<Download> = stream::iter(self.downloads()?)
+            .map(|d| async move {
+                match ctx.check_file(&d.path, d.size, &d.sha1).await {
+                    Ok(_) => None,
+                    Err(_) => Some(d)
+                }
+            })
+            .buffer_unordered(ctx.settings.asset_concurrency)
+            .filter_map(future::ready)
+            .collect()
+            .await;
+        let bytes = downloads.iter().map(|d| d.size).sum();
+        Ok(Plan { downloads, bytes })
+    }
+
+    // Fetches what `plan` lists, then extracts natives and writes the asset
+    // index, which is everything `ensure_all` would have done.
+    pub async fn execute(&self, ctx: &Context, plan: &Plan, cancel: &CancelToken) -> Report {
+        let lock = match lock(ctx).await { Ok(lock) => lock, Err(report) => return report };
+        let phase = |phase| plan.downloads.iter().filter(move |d: &&Download| d.phase == phase);
+        let libraries = async {
+            let mut report = fetch(ctx, Phase::Libraries, phase(Phase::Libraries), cancel).await;
+            report.merge(self.install_natives(ctx, &lock, cancel).await);
+            report
+        };
+        let assets = fetch(ctx, Phase::Assets, phase(Phase::Assets), cancel);
+        let (mut report, assets) = future::join(libraries, assets).await;
+        report.merge(assets);
+        if report.cancelled { return report }
+        if let Err(error) = self.write_index(ctx).await {
+            report.failed.push(Failure { path: self.assets.index_path(), error });
+        }
+        report
+    }
+}
+
+// The one download pipeline every install goes through: skips files that
+// already verify, and fetches the rest under `phase`'s scheduler priority,
+// concurrency limit and progress events.
+pub(super) async fn fetch<'a, I>(ctx: &Context, phase: Phase, downloads: I, cancel: &CancelToken) -> Report
+where
+    I: IntoIterator<Item = &'a Download>
+{
+    let downloads: Vec<&Download> = downloads.into_iter().collect();
+    let bytes = downloads.iter().map(|d| d.size).sum();
+    let tracker = Tracker::new(ctx, phase, downloads.len(), bytes);
+    let tracker = &tracker;
+    let limit = match phase {
+        Phase::Assets => ctx.settings.asset_concurrency,
+        _ => ctx.settings.library_concurrency
+    };
+    let failed: Vec<Failure> = stream::iter(downloads)
+        .map(|d| async move {
+            cancel.check().ok()?;
+            tracker.start(&d.path, d.size);
+            let result = match ctx.check_file(&d.path, d.size, &d.sha1).await {
+                Ok(_) => Ok(()),
+                Err(_) => async {
+                    let _permit = ctx.schedule(phase.into(), cancel).await?;
+                    ctx.download(&d.url, d.entry.as_deref(), &d.path, d.size, &d.sha1, cancel).await
+                }.await
+            };
+            tracker.finish(&d.path, d.size, result.is_ok());
+            result.err().map(|error| Failure { path: d.path.clone(), error })
+        })
+        .buffer_unordered(limit)
+        .filter_map(future::ready)
+        .collect()
+        .await;
+    let report = Report::new(failed, cancel);
+    tracker.done(report.failed.len());
+    report
+}
@@ -3,7 +3,8 @@ use serde_derive::Deserialize;
 #[derive(Debug, Deserialize)]
 pub struct Client {
     pub url: String,
-    pub size: u32
+    pub size: u32,
+    pub sha1: String
 }
 
 #[derive(Debug, Deserialize)]
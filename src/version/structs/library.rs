@@ -27,7 +27,9 @@ pub struct Downloads {
 pub struct Artifact {
     pub path: String,
     pub url: String,
-    pub size: u32
+    pub size: u32,
+    #[serde(default)]
+    pub sha1: String
 }
 
 impl Default for Artifact {
@@ -35,7 +37,8 @@ impl Default for Artifact {
         Self {
             path: String::new(),
             url: String::new(),
-            size: 0
+            size: 0,
+            sha1: String::new()
         }
     }
 }
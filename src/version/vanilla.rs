@@ -1,10 +1,9 @@
-use std::vec;
-use anyhow::{Result, anyhow};
+use std::{path::PathBuf, vec};
 use serde_derive::Deserialize;
 
-use crate::{manifest};
+use crate::{context::Context, error::{Error, Result}, manifest};
 use super::{
-    Assets, File, Version,
+    Assets, File, Native, Version,
     structs::{
         arguments::{Argument, Arguments, Value},
         asset_index::AssetIndex, downloads::Downloads, library::Library
@@ -35,9 +34,12 @@ struct Root {
     main_class: String,
 }
 
-pub async fn parse(url: &str) -> Result<Version> {
-    let root: Root = reqwest::get(url).await?.json().await?;
-    let mut assets: Assets = reqwest::get(root.asset_index.url).await?.json().await?;
+pub async fn parse(ctx: &Context, url: &str) -> Result<Version> {
+    let bytes = ctx.get_cached(url).await?;
+    let root: Root = serde_json::from_slice(&bytes).map_err(|e| Error::json(url, e))?;
+    let bytes = ctx.get_cached(&root.asset_index.url).await?;
+    let mut assets: Assets = serde_json::from_slice(&bytes)
+        .map_err(|e| Error::json(&root.asset_index.url, e))?;
     assets.id = root.asset_index.id;
     let mut game_args = Vec::new();
     let mut jvm_args = Vec::new();
@@ -100,45 +102,63 @@ pub async fn parse(url: &str) -> Result<Version> {
     }
     let mut libraries = vec![
         File {
-            path: format!("client/{}.jar", root.id),
+            path: PathBuf::from(format!("client/{}.jar", root.id)),
             url: root.downloads.client.url,
             size: root.downloads.client.size,
+            sha1: root.downloads.client.sha1,
+            entry: None
         }
     ];
     let mut natives = Vec::new();
-    root.libraries.into_iter().for_each(|mut l| {
+    for mut l in root.libraries {
         if l.rules.iter().find(|r| !r.calc()).is_none() {
             if l.downloads.artifact.url.len() > 0 {
                 libraries.push(File {
-                    path: l.downloads.artifact.path,
+                    path: PathBuf::from(l.downloads.artifact.path),
                     url: l.downloads.artifact.url,
-                    size: l.downloads.artifact.size
+                    size: l.downloads.artifact.size,
+                    sha1: l.downloads.artifact.sha1,
+                    entry: None
                 });
             }
             if l.natives.len() > 0 {
-                let key = l.natives
-                    .get(OS_NAME).unwrap()
-                    .replace("${arch}", OS_ARCH);
-                let artifact = l.downloads.classifiers.remove(&key).unwrap();
+                let key = match l.natives.get(OS_NAME) {
+                    Some(key) => key.replace("${arch}", OS_ARCH),
+                    None => return Err(Error::NativeNotFound {
+                        library: l.name, classifier: OS_NAME.to_string()
+                    })
+                };
+                let artifact = match l.downloads.classifiers.remove(&key) {
+                    Some(artifact) => artifact,
+                    None => return Err(Error::NativeNotFound {
+                        library: l.name, classifier: key
+                    })
+                };
                 libraries.push(File {
-                    path: artifact.path.clone(),
+                    path: PathBuf::from(&artifact.path),
                     url: artifact.url,
-                    size: artifact.size
+                    size: artifact.size,
+                    sha1: artifact.sha1,
+                    entry: None
                 });
-                if !l.extract.default { natives.push(artifact.path) };
+                if !l.extract.default {
+                    natives.push(Native { path: PathBuf::from(artifact.path), exclude: l.extract.exclude })
+                };
             }
         }
-    });
+    }
     Ok(Version {
-        id: root.id, main_class: root.main_class,
+        id: root.id, main_class: root.main_class, resolved: None,
         assets, game_args, jvm_args, libraries, natives
     })
 }
 
-pub async fn get(id: &str) -> Result<Version> {
-    let manifest = manifest::vanilla::get().await?;
-    match manifest.get(id) {
-        Some(v) => { Ok(parse(&v.url).await?) }
-        None => { Err(anyhow!("")) }
-    }
+pub async fn get(ctx: &Context, id: &str) -> Result<Version> {
+    Version::resolve(ctx, &format!("versions/{}.json", id), || async {
+        let manifest = manifest::vanilla::get(ctx).await?;
+        match manifest.get(id) {
+            Some(v) => { Ok(parse(ctx, &v.url).await?) }
+            None => { Err(Error::VersionNotFound { id: id.to_string() }) }
+        }
+    }).await
 }
\ No newline at end of file
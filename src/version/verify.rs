@@ -0,0 +1,158 @@
+use std::{collections::HashSet, io::ErrorKind, path::{Path, PathBuf}, slice};
+use futures::{future, stream, StreamExt};
+
+use crate::{
+    context::{CancelToken, Context, LockKind, Phase, RootLock},
+    error::{Error, Result},
+    gc
+};
+use super::{extract, lock, plan, Assets, Download, Failure, Report, Version};
+
+#[derive(Debug)]
+pub struct Problem {
+    pub phase: Phase,
+    pub path: PathBuf,
+    pub error: Error,
+}
+
+#[derive(Debug, Default)]
+pub struct Verification {
+    pub missing: Vec<Problem>,
+    pub corrupt: Vec<Problem>,
+    // Files under the managed directories that no installed version needs.
+    pub extra: Vec<PathBuf>,
+}
+
+impl Verification {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.corrupt.is_empty()
+    }
+
+    fn push(&mut self, problem: Problem) {
+        match &problem.error {
+            Error::Io { source, .. } if source.kind() == ErrorKind::NotFound => {
+                self.missing.push(problem)
+            }
+            _ => self.corrupt.push(problem)
+        }
+    }
+
+    fn broken(&self) -> HashSet<&Path> {
+        self.missing.iter().chain(&self.corrupt).map(|p| p.path.as_path()).collect()
+    }
+}
+
+impl Version {
+    // Checks every library, native, asset object and the asset index against
+    // what this version expects, without touching anything on disk. Files are
+    // only reported as extra when neither this version nor any of `installed`
+    // references them.
+    pub async fn verify(&self, ctx: &Context, installed: &[Version]) -> Result<Verification> {
+        let lock = ctx.lock(LockKind::Shared).await?;
+        let mut verification = self.check(ctx, &lock).await;
+        let mut keep = gc::referenced(ctx, installed).await?;
+        keep.extend(gc::referenced(ctx, slice::from_ref(self)).await?);
+        for dir in gc::DIRS.iter() {
+            for (path, _) in ctx.walk(dir).await? {
+                if !keep.contains(&path) { verification.extra.push(path) }
+            }
+        }
+        Ok(verification)
+    }
+
+    // The missing and corrupt half of `verify`, for callers that already hold
+    // the root, shared or not.
+    async fn check(&self, ctx: &Context, _lock: &RootLock) -> Verification {
+        let mut verification = Verification::default();
+        let downloads = match self.downloads() {
+            Ok(downloads) => downloads,
+            Err(error) => {
+                let index = self.assets.index_path();
+                verification.corrupt.push(Problem { phase: Phase::Assets, path: index, error });
+                self.library_downloads()
+            }
+        };
+        let problems: Vec<Problem> = stream::iter(downloads)
+            .map(|d| async move {
+                let result = ctx.check_file(&d.path, d.size, &d.sha1).await;
+                result.err().map(|error| Problem { phase: d.phase, path: d.path, error })
+            })
+            .buffer_unordered(ctx.settings.asset_concurrency)
+            .filter_map(future::ready)
+            .collect()
+            .await;
+        problems.into_iter().for_each(|p| verification.push(p));
+        for native in &self.natives {
+            let entries = match gc::native_entries(ctx, native).await {
+                Ok(entries) => entries,
+                // A jar that won't open is corrupt whatever its size says;
+                // `repair` fetches it again and re-extracts it.
+                Err(error) => {
+                    let jar = native.jar();
+                    if !verification.broken().contains(jar.as_path()) {
+                        verification.corrupt.push(Problem { phase: Phase::Natives, path: jar, error });
+                    }
+                    continue
+                }
+            };
+            for (path, size) in entries {
+                if let Err(error) = ctx.check_file_size(&path, size).await {
+                    verification.push(Problem { phase: Phase::Natives, path, error });
+                }
+            }
+        }
+        let index = self.assets.index_path();
+        if let Err(error) = self.check_index(ctx, &index).await {
+            verification.push(Problem { phase: Phase::Assets, path: index, error });
+        }
+        verification
+    }
+
+    async fn check_index(&self, ctx: &Context, index: &Path) -> Result<()> {
+        let bytes = ctx.read_file(index).await?;
+        let assets: Assets = serde_json::from_slice(&bytes)
+            .map_err(|e| Error::json(ctx.path(index).display(), e))?;
+        if assets.objects == self.assets.objects { Ok(()) }
+        else { Err(Error::Outdated { path: ctx.path(index) }) }
+    }
+
+    // Re-fetches or re-extracts only what `verify` finds missing or corrupt.
+    // Extra files are left alone; see `gc::collect` for removing them.
+    pub async fn repair(&self, ctx: &Context, cancel: &CancelToken) -> Report {
+        let lock = match lock(ctx).await { Ok(lock) => lock, Err(report) => return report };
+        let verification = self.check(ctx, &lock).await;
+        let broken = verification.broken();
+        // `check` has already reported an index with unusable hashes.
+        let downloads: Vec<Download> = self
+            .downloads()
+            .unwrap_or_else(|_| self.library_downloads())
+            .into_iter()
+            .filter(|d| broken.contains(d.path.as_path()))
+            .collect();
+        let phase = |phase| downloads.iter().filter(move |d: &&Download| d.phase == phase);
+        let (mut report, assets) = future::join(
+            plan::fetch(ctx, Phase::Libraries, phase(Phase::Libraries), cancel),
+            plan::fetch(ctx, Phase::Assets, phase(Phase::Assets), cancel)
+        ).await;
+        report.merge(assets);
+        let mut failed = report.failed;
+        for native in &self.natives {
+            if cancel.is_cancelled() { break }
+            let jar = native.jar();
+            let entries = gc::native_entries(ctx, native).await.unwrap_or_default();
+            let damaged = broken.contains(jar.as_path())
+                || entries.iter().any(|(path, _)| broken.contains(path.as_path()));
+            if !damaged { continue }
+            if let Err(error) = extract(ctx, native).await {
+                failed.push(Failure { path: jar, error });
+            }
+        }
+        let index = self.assets.index_path();
+        if broken.contains(index.as_path()) && !cancel.is_cancelled() {
+            if let Err(error) = self.write_index(ctx).await {
+                failed.push(Failure { path: index, error });
+            }
+        }
+        Report::new(failed, cancel)
+    }
+}